@@ -0,0 +1,65 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::git::Commit;
+
+/// Buckets the commits by author name, keeping their order within each bucket.
+pub fn group_by_author(commits: Vec<Commit>) -> HashMap<String, Vec<Commit>> {
+    let mut groups: HashMap<String, Vec<Commit>> = HashMap::new();
+
+    for commit in commits {
+        groups
+            .entry(commit.author.clone())
+            .or_default()
+            .push(commit);
+    }
+
+    groups
+}
+
+/// Counts how many of the commits changed each path, as read with the `files` option.
+pub fn file_touch_counts(commits: &[Commit]) -> HashMap<String, usize> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+
+    for commit in commits {
+        for path in &commit.files {
+            *counts.entry(path.clone()).or_default() += 1;
+        }
+    }
+
+    counts
+}
+
+/// Aggregates over a list of commits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComparisonStats {
+    pub total: usize,
+    /// The number of distinct author names.
+    pub authors: usize,
+    /// The date of the oldest commit, `None` without commits.
+    pub earliest: Option<String>,
+    /// The date of the newest commit, `None` without commits.
+    pub latest: Option<String>,
+}
+
+pub fn stats(commits: &[Commit]) -> ComparisonStats {
+    let authors: HashSet<&str> = commits
+        .iter()
+        .map(|commit| commit.author.as_str())
+        .collect();
+
+    // Dates are formatted as `%Y-%m-%d`, so they compare as plain strings.
+    ComparisonStats {
+        total: commits.len(),
+        authors: authors.len(),
+        earliest: commits
+            .iter()
+            .map(|commit| &commit.author_date)
+            .min()
+            .cloned(),
+        latest: commits
+            .iter()
+            .map(|commit| &commit.author_date)
+            .max()
+            .cloned(),
+    }
+}
@@ -5,18 +5,288 @@ use structopt::StructOpt;
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "fdiff",
-    about = "Print the commits in the `branch1` that aren't present in the `branch2`"
+    about = "Print the commits in the `ref1` that aren't present in the `ref2`"
 )]
 pub struct CliArgs {
-    #[structopt(required = true, value_name = "branch1")]
-    pub branch1: String,
+    #[structopt(required = true, value_name = "ref1")]
+    pub ref1: String,
 
-    #[structopt(required = true, value_name = "branch2")]
-    pub branch2: String,
+    #[structopt(required = true, value_name = "ref2")]
+    pub ref2: String,
 
     #[structopt(short = "e", long = "exclude", value_name = "exclude")]
     pub exclude: Option<Vec<String>>,
 
+    /// Read more exclude entries from a file, one per line
+    #[structopt(long = "exclude-file", value_name = "path", parse(from_os_str))]
+    pub exclude_file: Option<PathBuf>,
+
+    /// Only keep the commits whose summary matches any of these entries
+    #[structopt(long = "include", value_name = "include")]
+    pub include: Option<Vec<String>>,
+
+    /// Treat the exclude and include entries as regular expressions
+    #[structopt(short = "r", long = "regex")]
+    pub regex: bool,
+
+    /// Ignore case when matching the exclude and include entries
+    #[structopt(short = "i", long = "ignore-case")]
+    pub ignore_case: bool,
+
+    /// Only match the exclude and include entries as whole words
+    #[structopt(long = "whole-word", conflicts_with = "regex")]
+    pub whole_word: bool,
+
+    /// Match commits by the content of their diffs instead of by their summaries
+    #[structopt(long = "patch-id", conflicts_with = "fuzzy")]
+    pub patch_id: bool,
+
+    /// Match commits by their Change-Id trailer, as used by Gerrit
+    #[structopt(long = "change-id", conflicts_with_all = &["patch-id", "fuzzy"])]
+    pub change_id: bool,
+
+    /// Let git match the commits with --cherry-mark
+    #[structopt(
+        long = "cherry-mark",
+        conflicts_with_all = &["patch-id", "change-id", "fuzzy"]
+    )]
+    pub cherry_mark: bool,
+
+    /// Match commits that leave the files in the same state, by their tree hashes
+    #[structopt(
+        long = "tree-hash",
+        conflicts_with_all = &["patch-id", "change-id", "cherry-mark", "fuzzy"]
+    )]
+    pub tree_hash: bool,
+
+    /// Match commits whose summaries are at least `threshold` similar, from 0.0 to 1.0
+    #[structopt(long = "fuzzy", value_name = "threshold")]
+    pub fuzzy: Option<f64>,
+
+    /// Only compare commits more recent than the given date
+    #[structopt(long = "since", value_name = "date")]
+    pub since: Option<String>,
+
+    /// Only compare commits older than the given date
+    #[structopt(long = "until", value_name = "date")]
+    pub until: Option<String>,
+
+    /// Apply --since and --until to the author dates instead of the commit dates
+    #[structopt(long = "author-date")]
+    pub author_date: bool,
+
+    /// Only compare the commits whose author name or email matches the pattern
+    #[structopt(short = "a", long = "author", value_name = "pattern")]
+    pub author: Option<String>,
+
+    /// Leave out the commits whose author name or email is any of these
+    #[structopt(long = "exclude-author", value_name = "author")]
+    pub exclude_author: Option<Vec<String>>,
+
+    /// Leave out the commits authored with your configured user.email
+    #[structopt(long = "exclude-mine")]
+    pub exclude_mine: bool,
+
+    /// Replace the author names with pseudonyms like author-1
+    #[structopt(long = "anonymize")]
+    pub anonymize: bool,
+
+    /// Mask tokens, keys and passwords in the printed summaries with ***
+    #[structopt(long = "redact")]
+    pub redact: bool,
+
+    /// Match summaries by their first `length` characters only
+    #[structopt(long = "key-length", value_name = "length")]
+    pub key_length: Option<usize>,
+
+    /// Match summaries by the part before the first `delimiter` only
+    #[structopt(
+        long = "key-delimiter",
+        value_name = "delimiter",
+        conflicts_with = "key-length"
+    )]
+    pub key_delimiter: Option<String>,
+
+    /// Only read the newest `count` commits of each ref
+    #[structopt(short = "n", long = "max-count", value_name = "count")]
+    pub max_count: Option<usize>,
+
+    /// Skip the newest `count` commits of each ref
+    #[structopt(long = "skip", value_name = "count")]
+    pub skip: Option<usize>,
+
+    /// Leave out the commits changing fewer than `count` files
+    #[structopt(long = "min-files", value_name = "count")]
+    pub min_files: Option<usize>,
+
+    /// Leave out the commits adding and removing fewer than `count` lines
+    #[structopt(long = "min-lines", value_name = "count")]
+    pub min_lines: Option<usize>,
+
+    /// Only compare the commits that touch the path, can be given more than once
+    #[structopt(long = "path", value_name = "path", number_of_values = 1)]
+    pub paths: Vec<String>,
+
+    /// Follow the history of the path past renames, only with a single --path
+    #[structopt(long = "follow")]
+    pub follow: bool,
+
+    /// Only list the Conventional Commits of the type, such as `feat`, can be given more than once
+    #[structopt(long = "type", value_name = "type", number_of_values = 1)]
+    pub types: Vec<String>,
+
+    /// Match Conventional Commits summaries by their description, ignoring the type and scope
+    #[structopt(long = "ignore-type-prefix")]
+    pub ignore_type_prefix: bool,
+
+    /// Only list the commits whose body contains `text`
+    #[structopt(long = "body-contains", value_name = "text")]
+    pub body_contains: Option<String>,
+
+    /// Leave out the merge commits
+    #[structopt(long = "no-merges", conflicts_with = "merges")]
+    pub no_merges: bool,
+
+    /// Only compare the merge commits
+    #[structopt(long = "merges")]
+    pub merges: bool,
+
+    /// Only follow the first parent of merge commits, skipping the history they merged in
+    #[structopt(long = "first-parent")]
+    pub first_parent: bool,
+
+    /// Ignore differences in whitespace when matching summaries
+    #[structopt(short = "w", long = "ignore-whitespace")]
+    pub ignore_whitespace: bool,
+
+    /// Sort the commits from oldest to newest
+    #[structopt(long = "oldest-first", conflicts_with = "newest-first")]
+    pub oldest_first: bool,
+
+    /// Sort the commits from newest to oldest
+    #[structopt(long = "newest-first")]
+    pub newest_first: bool,
+
+    /// Skip the history shared by both branches, which is faster on large repositories
+    #[structopt(long = "divergent-only")]
+    pub divergent_only: bool,
+
+    /// Path of the git executable to use instead of the one on PATH
+    #[structopt(
+        long = "git",
+        value_name = "path",
+        parse(from_os_str),
+        env = "DIFF_GIT_BINARY"
+    )]
+    pub git_binary: Option<PathBuf>,
+
+    /// Kill any git command that runs longer than `seconds` and fail
+    #[structopt(long = "timeout", value_name = "seconds")]
+    pub timeout: Option<u64>,
+
+    /// Retry git log up to `count` times when it fails on a lock or a filesystem hiccup
+    #[structopt(long = "retries", value_name = "count", default_value = "0")]
+    pub retries: usize,
+
+    /// Fetch the remotes of remote-tracking refs like origin/main before comparing
+    #[structopt(long = "fetch")]
+    pub fetch: bool,
+
+    /// Fail if tracked files have uncommitted changes
+    #[structopt(long = "require-clean")]
+    pub require_clean: bool,
+
+    /// Print the git commands to stderr as they run, and how many commits they read
+    #[structopt(short = "v", long = "verbose")]
+    pub verbose: bool,
+
+    /// Don't print any warnings
+    #[structopt(short = "q", long = "quiet", conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Leave out the commits that were reverted along with their reverts
+    #[structopt(long = "collapse-reverts")]
+    pub collapse_reverts: bool,
+
+    /// Mark the commits that were likely squashed into a commit of the other ref
+    #[structopt(long = "detect-squashes")]
+    pub detect_squashes: bool,
+
+    /// Only compare the commits made since the merge base of the refs
+    #[structopt(long = "since-merge-base", conflicts_with = "divergent-only")]
+    pub since_merge_base: bool,
+
+    /// Compare refs even when their histories are unrelated
+    #[structopt(long = "allow-unrelated")]
+    pub allow_unrelated: bool,
+
+    /// Fail instead of comparing HEAD when it's detached, such as during a rebase
+    #[structopt(long = "reject-detached")]
+    pub reject_detached: bool,
+
+    /// Leave out the commits whose summary looks like a merge's, such as "Merge pull request #1"
+    #[structopt(long = "skip-merge-summaries")]
+    pub skip_merge_summaries: bool,
+
+    /// List each summary once, keeping its most recent commit
+    #[structopt(long = "dedupe")]
+    pub dedupe: bool,
+
+    /// List the commits unique to either ref, marked with < for ref1 and > for ref2
+    #[structopt(long = "left-right", conflicts_with = "count")]
+    pub left_right: bool,
+
+    /// Only check that the repository, the refs and the exclude patterns are valid
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Only print how many commits ref1 is ahead and behind ref2
+    #[structopt(long = "count")]
+    pub count: bool,
+
+    /// Print the branches and tags pointing at each commit
+    #[structopt(long = "refs")]
+    pub refs: bool,
+
+    /// Read the full commit hashes instead of the abbreviated ones
+    #[structopt(long = "full-hash")]
+    pub full_hash: bool,
+
+    /// Print the dates relative to now, such as `3 days ago`
+    #[structopt(long = "relative-date")]
+    pub relative_date: bool,
+
+    /// Print the number of commits, authors, and the date range above the commits
+    #[structopt(long = "stats")]
+    pub stats: bool,
+
+    /// Print the commits as a Markdown table
+    #[structopt(long = "markdown")]
+    pub markdown: bool,
+
+    /// Print the commits as a standalone HTML page
+    #[structopt(long = "html", conflicts_with = "markdown")]
+    pub html: bool,
+
+    /// Print only the summaries of the commits, without their dates
+    #[structopt(long = "summaries-only", conflicts_with_all = &["markdown", "html"])]
+    pub summaries_only: bool,
+
+    /// Print the commits as CSV
+    #[cfg(feature = "csv")]
+    #[structopt(long = "csv")]
+    pub csv: bool,
+
+    /// Print the commits as a JSON array
+    #[cfg(feature = "serde")]
+    #[structopt(long = "json")]
+    pub json: bool,
+
+    /// Print the commits as JSON Lines, one object per line
+    #[cfg(feature = "serde")]
+    #[structopt(long = "ndjson", conflicts_with = "json")]
+    pub ndjson: bool,
+
     #[structopt(parse(from_os_str))]
     pub repo_path: Option<PathBuf>,
 }
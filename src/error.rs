@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The ways a comparison can fail.
+#[derive(Debug)]
+pub enum DiffError {
+    /// The directory isn't inside a Git repository.
+    NotARepo,
+    /// The repository path doesn't exist or isn't a directory.
+    InvalidRepoPath(PathBuf),
+    /// The ref doesn't resolve to a commit.
+    RefNotFound(String),
+    /// The revision range names a ref that doesn't resolve to a commit, or isn't a range.
+    InvalidRange(String),
+    /// The ref has no commits left once the log filters other than `paths` are applied.
+    EmptyRef(String),
+    /// Commits by the current user were excluded, but `user.email` isn't configured.
+    UserEmailNotSet,
+    /// The branch has no upstream configured.
+    NoUpstream(String),
+    /// The refs have unrelated histories, without a common ancestor.
+    NoCommonAncestor(String, String),
+    /// `require_clean` was set, but this many tracked files have uncommitted changes.
+    DirtyWorkingTree(usize),
+    /// The ref is `HEAD`, detached at this commit, and `reject_detached_head` was set.
+    DetachedHead(String),
+    /// A record or field separator isn't an ASCII control character the `git log` output leaves
+    /// free.
+    InvalidSeparator(char),
+    /// The threshold of `MatchStrategy::Fuzzy` isn't between `0.0` and `1.0`.
+    InvalidThreshold(f64),
+    /// `follow` was set with this many paths instead of exactly one.
+    FollowNeedsOnePath(usize),
+    /// The `git` executable couldn't be found.
+    GitNotInstalled,
+    /// Git exited unsuccessfully. `code` is `None` when it was killed by a signal.
+    GitFailed {
+        code: Option<i32>,
+        stderr: String,
+    },
+    /// A git command ran longer than the timeout and was killed.
+    Timeout(Duration),
+    /// The output of git couldn't be parsed.
+    Parse(String),
+    /// An exclude entry isn't a valid regular expression.
+    InvalidPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    Io(io::Error),
+    /// libgit2 failed to read the repository, with the `git2` feature.
+    #[cfg(feature = "git2")]
+    Libgit2(git2::Error),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotARepo => write!(f, "Not inside a Git repository"),
+            Self::InvalidRepoPath(path) => {
+                write!(
+                    f,
+                    "Repository path '{}' isn't an existing directory",
+                    path.display()
+                )
+            }
+            Self::RefNotFound(ref_) => write!(f, "Ref '{ref_}' doesn't resolve to a commit"),
+            Self::InvalidRange(range) => {
+                write!(f, "Range '{range}' isn't a valid revision range")
+            }
+            Self::EmptyRef(ref_) => write!(f, "Ref '{ref_}' has no commits to compare against"),
+            Self::NoUpstream(branch) => write!(
+                f,
+                "'{branch}' has no upstream; set one with `git branch --set-upstream-to`"
+            ),
+            Self::NoCommonAncestor(ref1, ref2) => write!(
+                f,
+                "'{ref1}' and '{ref2}' have no common ancestor; their histories are unrelated"
+            ),
+            Self::DirtyWorkingTree(1) => write!(
+                f,
+                "1 tracked file has uncommitted changes; commit or stash it first"
+            ),
+            Self::DirtyWorkingTree(count) => write!(
+                f,
+                "{count} tracked files have uncommitted changes; commit or stash them first"
+            ),
+            Self::DetachedHead(hash) => write!(
+                f,
+                "HEAD is detached at {hash}; check out a branch or pass the commit instead"
+            ),
+            Self::InvalidSeparator(separator) => write!(
+                f,
+                "{separator:?} can't separate the git log output; pick another control character"
+            ),
+            Self::InvalidThreshold(threshold) => write!(
+                f,
+                "The fuzzy threshold {threshold} isn't between 0.0 and 1.0"
+            ),
+            Self::FollowNeedsOnePath(count) => write!(
+                f,
+                "Following renames needs exactly one path, but {count} were given"
+            ),
+            Self::UserEmailNotSet => write!(
+                f,
+                "user.email isn't set; set it with `git config user.email <email>`"
+            ),
+            Self::GitNotInstalled => {
+                write!(f, "git executable not found; is git installed and on PATH?")
+            }
+            Self::GitFailed {
+                code: Some(code),
+                stderr,
+            } => write!(f, "git exited with code {code}: {stderr}"),
+            Self::GitFailed { code: None, stderr } => {
+                write!(f, "git was terminated by a signal: {stderr}")
+            }
+            Self::Timeout(timeout) => write!(f, "git didn't finish within {timeout:?}"),
+            Self::Parse(message) => write!(f, "Couldn't parse the git output: {message}"),
+            Self::InvalidPattern { pattern, source } => {
+                write!(f, "Invalid exclude pattern '{pattern}': {source}")
+            }
+            Self::Io(err) => write!(f, "{err}"),
+            #[cfg(feature = "git2")]
+            Self::Libgit2(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for DiffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidPattern { source, .. } => Some(source),
+            Self::Io(err) => Some(err),
+            #[cfg(feature = "git2")]
+            Self::Libgit2(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DiffError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[cfg(feature = "git2")]
+impl From<git2::Error> for DiffError {
+    fn from(err: git2::Error) -> Self {
+        Self::Libgit2(err)
+    }
+}
@@ -0,0 +1,276 @@
+#[cfg(any(feature = "csv", feature = "serde"))]
+use std::error::Error;
+use std::io::{self, Write};
+
+use crate::git::Commit;
+
+/// The formats `write_report` and `ReportWriter` write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// `date: summary` lines, like the uncolored output of the CLI.
+    Plain,
+    /// A JSON array of objects, like `to_json`.
+    #[cfg(feature = "serde")]
+    Json,
+    /// CSV with a header row, like `to_csv`.
+    #[cfg(feature = "csv")]
+    Csv,
+    /// A Markdown table, like `to_markdown_table`.
+    Markdown,
+}
+
+/// Writes the commits to `writer` in `format`, without building the whole report in memory
+/// first, and flushes it.
+pub fn write_report<W: Write>(
+    commits: &[Commit],
+    writer: W,
+    format: ReportFormat,
+) -> io::Result<()> {
+    let mut report = ReportWriter::new(writer, format)?;
+
+    for commit in commits {
+        report.write_commit(commit)?;
+    }
+
+    report.finish()?;
+
+    Ok(())
+}
+
+/// Writes a report one commit at a time, such as the commits of `compare_branches_iter` as
+/// they're found, so the header and the JSON brackets are only written once.
+#[derive(Debug)]
+pub struct ReportWriter<W: Write> {
+    writer: W,
+    format: ReportFormat,
+    written: usize,
+}
+
+impl<W: Write> ReportWriter<W> {
+    /// Starts a report in `format`, writing its header if it has one.
+    pub fn new(mut writer: W, format: ReportFormat) -> io::Result<Self> {
+        match format {
+            #[cfg(feature = "csv")]
+            ReportFormat::Csv => write_csv_record(&mut writer, CSV_HEADER)?,
+            ReportFormat::Markdown => writer.write_all(MARKDOWN_HEADER.as_bytes())?,
+            _ => {}
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            written: 0,
+        })
+    }
+
+    /// Writes `commit` as the next entry of the report.
+    pub fn write_commit(&mut self, commit: &Commit) -> io::Result<()> {
+        match self.format {
+            ReportFormat::Plain => writeln!(
+                self.writer,
+                "{}: {}{}",
+                commit.display_date(),
+                commit.summary,
+                annotations(commit)
+            )?,
+            // Indented like an element of the array `to_json` pretty-prints.
+            #[cfg(feature = "serde")]
+            ReportFormat::Json => {
+                let separator = if self.written == 0 { "[\n" } else { ",\n" };
+                let json = serde_json::to_string_pretty(commit)?;
+                let indented = json.lines().collect::<Vec<&str>>().join("\n  ");
+                write!(self.writer, "{separator}  {indented}")?;
+            }
+            #[cfg(feature = "csv")]
+            ReportFormat::Csv => write_csv_record(&mut self.writer, csv_record(commit))?,
+            ReportFormat::Markdown => self.writer.write_all(markdown_row(commit).as_bytes())?,
+        }
+        self.written += 1;
+
+        Ok(())
+    }
+
+    /// Ends the report, closing the JSON array, flushes the writer and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        #[cfg(feature = "serde")]
+        if self.format == ReportFormat::Json {
+            let end = if self.written == 0 { "[]\n" } else { "\n]\n" };
+            self.writer.write_all(end.as_bytes())?;
+        }
+
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+/// Serializes the commits as a JSON array of objects.
+#[cfg(feature = "serde")]
+pub fn to_json(commits: &[Commit]) -> Result<String, Box<dyn Error>> {
+    Ok(serde_json::to_string_pretty(commits)?)
+}
+
+/// Serializes the commits as JSON Lines, one compact object per line, each line ending with a
+/// newline.
+#[cfg(feature = "serde")]
+pub fn to_ndjson(commits: &[Commit]) -> Result<String, Box<dyn Error>> {
+    let mut ndjson = String::new();
+
+    for commit in commits {
+        ndjson.push_str(&serde_json::to_string(commit)?);
+        ndjson.push('\n');
+    }
+
+    Ok(ndjson)
+}
+
+/// Returns the summaries of the commits, in the same order.
+pub fn summaries_only(commits: &[Commit]) -> Vec<String> {
+    commits
+        .iter()
+        .map(|commit| commit.summary.clone())
+        .collect()
+}
+
+/// Renders the commits as a GitHub-flavored Markdown table with a Date and a Summary column.
+pub fn to_markdown_table(commits: &[Commit]) -> String {
+    let mut table = String::from(MARKDOWN_HEADER);
+
+    for commit in commits {
+        table.push_str(&markdown_row(commit));
+    }
+
+    table
+}
+
+const MARKDOWN_HEADER: &str = "| Date | Summary |\n| --- | --- |\n";
+
+fn markdown_row(commit: &Commit) -> String {
+    format!(
+        "| {} | {} |\n",
+        commit.author_date,
+        commit.summary.replace('|', "\\|")
+    )
+}
+
+/// Renders the commits as a standalone HTML page titled `title`, with a table of dates and
+/// summaries styled by inline CSS.
+pub fn to_html(commits: &[Commit], title: &str) -> String {
+    let title = escape_html(title);
+    let mut html = format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<style>
+body {{ font-family: -apple-system, \"Segoe UI\", Helvetica, Arial, sans-serif; margin: 2em; color: #24292f; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #d0d7de; padding: 6px 13px; text-align: left; }}
+th {{ background: #f6f8fa; }}
+tr:nth-child(even) td {{ background: #f6f8fa; }}
+td:first-child {{ white-space: nowrap; color: #57606a; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<table>
+<thead><tr><th>Date</th><th>Summary</th></tr></thead>
+<tbody>
+"
+    );
+
+    for commit in commits {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&commit.author_date),
+            escape_html(&commit.summary)
+        ));
+    }
+
+    html.push_str("</tbody>\n</table>\n</body>\n</html>\n");
+
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders the commits as CSV with a header row, quoting the fields that need it.
+#[cfg(feature = "csv")]
+pub fn to_csv(commits: &[Commit]) -> Result<String, Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(CSV_HEADER)?;
+
+    for commit in commits {
+        writer.write_record(csv_record(commit))?;
+    }
+
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+#[cfg(feature = "csv")]
+const CSV_HEADER: [&str; 4] = ["hash", "date", "author", "summary"];
+
+#[cfg(feature = "csv")]
+fn csv_record(commit: &Commit) -> [&str; 4] {
+    [
+        &commit.hash,
+        &commit.author_date,
+        &commit.author,
+        &commit.summary,
+    ]
+}
+
+// The `csv` writer buffers what it writes, so it's flushed right away rather than kept around.
+#[cfg(feature = "csv")]
+fn write_csv_record<W: Write>(writer: &mut W, record: [&str; 4]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(record)?;
+    writer.flush()
+}
+
+/// Prints the commits as `date: summary` lines, with the date dimmed and the summary in green
+/// when stdout is a terminal and `NO_COLOR` isn't set to a non-empty value.
+#[cfg(feature = "color")]
+pub fn print_colored(commits: &[Commit]) {
+    use owo_colors::OwoColorize;
+    use std::io::IsTerminal;
+
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let colorize = !no_color && std::io::stdout().is_terminal();
+
+    for commit in commits {
+        let annotations = annotations(commit);
+
+        if colorize {
+            println!(
+                "{}: {}{}",
+                commit.display_date().dimmed(),
+                commit.summary.green(),
+                annotations.dimmed()
+            );
+        } else {
+            println!("{}: {}{annotations}", commit.display_date(), commit.summary);
+        }
+    }
+}
+
+/// Returns what is printed after the summary of `commit`, such as ` (v1.2.0) (squashed into
+/// abc1234)` for its refs and the commit it was squashed into. Empty without either.
+pub fn annotations(commit: &Commit) -> String {
+    let mut annotations = String::new();
+
+    if !commit.refs.is_empty() {
+        annotations.push_str(&format!(" ({})", commit.refs.join(", ")));
+    }
+    if let Some(hash) = &commit.squashed_into {
+        annotations.push_str(&format!(" (squashed into {hash})"));
+    }
+
+    annotations
+}
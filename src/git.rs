@@ -1,121 +1,4081 @@
-use std::collections::HashSet;
-use std::error::Error;
-use std::path::PathBuf;
-use std::process::{Command, Output};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Output, Stdio};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
+use regex::{Regex, RegexBuilder};
+
+use crate::error::DiffError;
+use crate::options::{
+    self, CompareOptions, DateKind, ExcludeMode, KeyPrefix, MatchStrategy, MergeFilter,
+    ProgressEvent, SortOrder, Verbosity,
+};
+#[cfg(feature = "git2")]
+use crate::revwalk;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Commit {
-    pub _id: String,
-    pub date: String,
+    pub hash: String,
+    /// The author date, which rebases and cherry-picks keep. Serialized as `date`, as it was
+    /// before the commit date could be read too.
+    #[cfg_attr(feature = "serde", serde(rename = "date"))]
+    pub author_date: String,
+    /// The `author_date` parsed as a calendar date, or `None` if it couldn't be parsed.
+    #[cfg(feature = "chrono")]
+    pub parsed_date: Option<chrono::NaiveDate>,
+    pub author: String,
+    /// The author email, only read when the `exclude_authors`, `exclude_mine` or
+    /// `anonymize_authors` option is set, and cleared again by the latter.
+    pub email: Option<String>,
     pub summary: String,
+    /// Lines added by the commit, only counted when the `numstat` or `files` option is set.
+    pub additions: usize,
+    /// Lines removed by the commit, only counted when the `numstat` or `files` option is set.
+    pub deletions: usize,
+    /// The paths the commit changed, only read when the `numstat` or `files` option is set.
+    /// Empty for merges, whose diffs `git log` doesn't show.
+    pub files: Vec<String>,
+    /// The message after the summary, only read when the `body` option is set. `None` when the
+    /// commit has no body too.
+    pub body: Option<String>,
+    /// The date relative to now, such as `3 days ago`, only read when the `relative_dates` option
+    /// is set. Unlike `author_date`, it doesn't sort as a string.
+    pub relative_date: Option<String>,
+    /// The commit date, formatted like `author_date`, only read when the `commit_dates` option
+    /// is set. Rebases and cherry-picks renew it, unlike `author_date`.
+    pub commit_date: Option<String>,
+    /// The names of the branches and tags pointing at the commit, such as `main` and `v1.2.0`,
+    /// only read when the `decorations` option is set. `HEAD` is listed too when it points at it.
+    pub refs: Vec<String>,
+    /// The full hash of the tree of the commit, only read with the `TreeHash` match strategy.
+    pub tree: Option<String>,
+    /// Whether the commit is signed and how its signature checked out, only read when the
+    /// `signatures` option is set.
+    pub signature: Option<SignatureStatus>,
+    /// The expansions of the `extra_placeholders` option, in the same order.
+    pub extra: Vec<String>,
+    /// The hash of the commit of the other ref this unique commit was likely squashed into, only
+    /// looked for when the `detect_squashes` option is set.
+    pub squashed_into: Option<String>,
+}
+
+/// Separates the fields of each commit of the `git log` output, the ASCII unit separator since
+/// unlike `|` it doesn't show up in names and summaries, unless the `field_separator` option
+/// replaces it.
+pub const FIELD_SEPARATOR: char = '\x1f';
+
+/// Separates the author email, which follows it, from the rest of a commit of the `git log`
+/// output.
+const EMAIL_SEPARATOR: char = '\x1b';
+
+/// Separates the relative date, which follows it, from the rest of a commit of the `git log`
+/// output.
+const RELATIVE_DATE_SEPARATOR: char = '\x1c';
+
+/// Separates the commit date, which follows it, from the rest of a commit of the `git log` output.
+const COMMIT_DATE_SEPARATOR: char = '\x18';
+
+/// Separates the decorations, which follow it, from the rest of a commit of the `git log` output.
+const REFS_SEPARATOR: char = '\x1a';
+
+/// Separates the tree hash, which follows it, from the rest of a commit of the `git log` output.
+const TREE_SEPARATOR: char = '\x19';
+
+/// Separates the signature status, which follows it, from the rest of a commit of the `git log`
+/// output.
+const SIGNATURE_SEPARATOR: char = '\x17';
+
+/// Separates the expansions of the extra placeholders, which follow it, from the rest of a commit
+/// of the `git log` output.
+const EXTRA_SEPARATOR: char = '\x1d';
+
+/// Surrounds every commit of the `git log` output, so the bodies and the extra placeholders can
+/// span several lines. NUL, since git cuts commit messages at the first one, so no message
+/// contains it, unless the `record_separator` option replaces it.
+const RECORD_SEPARATOR: char = '\0';
+
+/// The fields `log_command_with` asks git for after the summary, so the parsers only split off
+/// the separators git was asked to write. A summary can have any of those bytes itself, like the
+/// escape of an ANSI color code.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    record_separator: char,
+    field_separator: char,
+    body: bool,
+    email: bool,
+    relative_date: bool,
+    commit_date: bool,
+    refs: bool,
+    tree: bool,
+    signature: bool,
+    extra: bool,
+}
+
+impl Layout {
+    fn new(opts: &CompareOptions) -> Self {
+        Self {
+            record_separator: opts.record_separator.unwrap_or(RECORD_SEPARATOR),
+            field_separator: opts.field_separator.unwrap_or(FIELD_SEPARATOR),
+            body: opts.reads_body(),
+            email: opts.reads_email(),
+            relative_date: opts.relative_dates,
+            commit_date: opts.commit_dates,
+            refs: opts.decorations,
+            tree: opts.reads_tree(),
+            signature: opts.signatures,
+            extra: !opts.extra_placeholders.is_empty(),
+        }
+    }
 }
 
 impl Commit {
-    pub fn new(msg: String) -> Self {
-        let fields: Vec<&str> = msg.split('|').collect();
-        let _id = fields[0].to_string();
+    /// Parses a `git log` line of hash, date, author and summary separated by `FIELD_SEPARATOR`,
+    /// returning `None` if it doesn't have every field.
+    pub fn new(msg: String) -> Option<Self> {
+        Self::from_fields(&msg, FIELD_SEPARATOR)
+    }
+
+    fn from_fields(msg: &str, separator: char) -> Option<Self> {
+        // The summary is the last field, so any separator left belongs to it.
+        let fields: Vec<&str> = msg.splitn(4, separator).collect();
+        if fields.len() < 4 {
+            return None;
+        }
+
+        let hash = fields[0].to_string();
+
+        let author_date = fields[1].to_string();
+        let author = fields[2].to_string();
+        // A line read from a file with CRLF line endings would otherwise keep its `\r`, and
+        // no longer match the same summary read elsewhere.
+        let summary = fields[3].trim_end_matches('\r').to_string();
+
+        Some(Self {
+            hash,
+            #[cfg(feature = "chrono")]
+            parsed_date: parse_date(&author_date),
+            author_date,
+            author,
+            summary,
+            additions: 0,
+            deletions: 0,
+            files: Vec::new(),
+            body: None,
+            email: None,
+            relative_date: None,
+            commit_date: None,
+            refs: Vec::new(),
+            tree: None,
+            signature: None,
+            extra: Vec::new(),
+            squashed_into: None,
+        })
+    }
+
+    /// Parses a commit of the output of `log_command` with `opts`, the text between two record
+    /// separators, along with its body and the fields the options add after the summary, failing
+    /// with `DiffError::Parse` if it doesn't have every field. Unlike `parse_git_output`,
+    /// nothing is filtered out.
+    pub fn from_log_line(line: &str, opts: &CompareOptions) -> Result<Self, DiffError> {
+        parse_record(line, &Layout::new(opts)).ok_or_else(|| {
+            DiffError::Parse(format!(
+                "{line:?} doesn't have a hash, date, author and summary"
+            ))
+        })
+    }
+
+    /// The relative date when it was read, otherwise the date.
+    pub fn display_date(&self) -> &str {
+        self.relative_date.as_deref().unwrap_or(&self.author_date)
+    }
+
+    /// Parses the `type(scope)!: description` prefix of a Conventional Commits summary, returning
+    /// `None` if the summary doesn't follow it.
+    pub fn conventional(&self) -> Option<ConventionalInfo> {
+        parse_conventional(&self.summary)
+    }
+}
 
-        let summary = fields[2].to_string();
-        let date = fields[1].to_string();
+fn parse_conventional(summary: &str) -> Option<ConventionalInfo> {
+    let (prefix, description) = summary.split_once(": ")?;
 
-        Self { _id, date, summary }
+    let (prefix, is_breaking) = match prefix.strip_suffix('!') {
+        Some(prefix) => (prefix, true),
+        None => (prefix, false),
+    };
+
+    let (commit_type, scope) = match prefix.split_once('(') {
+        Some((commit_type, scope)) => (commit_type, Some(scope.strip_suffix(')')?)),
+        None => (prefix, None),
+    };
+
+    let is_word = |word: &str| {
+        !word.is_empty()
+            && word
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    if !is_word(commit_type) || scope.is_some_and(|scope| scope.is_empty()) {
+        return None;
+    }
+
+    let description = description.trim();
+    if description.is_empty() {
+        return None;
+    }
+
+    Some(ConventionalInfo {
+        commit_type: commit_type.to_string(),
+        scope: scope.map(str::to_string),
+        is_breaking,
+        description: description.to_string(),
+    })
+}
+
+/// The signature of a commit as `git log` checks it, its `%G?` placeholder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SignatureStatus {
+    /// A good signature by a trusted key, `G`.
+    Good,
+    /// A bad signature, `B`.
+    Bad,
+    /// A good signature by a key of unknown validity, `U`.
+    UnknownValidity,
+    /// A good signature that has expired, `X`.
+    Expired,
+    /// A good signature by an expired key, `Y`.
+    ExpiredKey,
+    /// A good signature by a revoked key, `R`.
+    RevokedKey,
+    /// A signature that couldn't be checked, such as without the key or `gpg`, `E`.
+    CannotCheck,
+    /// No signature, `N`.
+    Unsigned,
+    /// A status this version doesn't know, or none at all from a git that doesn't support `%G?`.
+    Unknown,
+}
+
+impl SignatureStatus {
+    fn from_placeholder(status: &str) -> Self {
+        match status.trim() {
+            "G" => Self::Good,
+            "B" => Self::Bad,
+            "U" => Self::UnknownValidity,
+            "X" => Self::Expired,
+            "Y" => Self::ExpiredKey,
+            "R" => Self::RevokedKey,
+            "E" => Self::CannotCheck,
+            "N" => Self::Unsigned,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// The parts of a Conventional Commits summary, such as `feat(api)!: drop the v1 endpoints`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConventionalInfo {
+    /// The type as written, such as `feat` or `fix`.
+    pub commit_type: String,
+    pub scope: Option<String>,
+    /// Whether the type is followed by `!`. A `BREAKING CHANGE:` footer isn't detected since only
+    /// the summary is read.
+    pub is_breaking: bool,
+    pub description: String,
+}
+
+#[cfg(feature = "chrono")]
+fn parse_date(date: &str) -> Option<chrono::NaiveDate> {
+    match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(parsed_date) => Some(parsed_date),
+        Err(err) => {
+            eprintln!("Warning: couldn't parse the commit date '{date}': {err}");
+            None
+        }
     }
 }
 
+/// The commits that are unique to each side of a comparison.
+#[derive(Debug)]
+pub struct BranchDiff {
+    pub only_in_branch1: Vec<Commit>,
+    pub only_in_branch2: Vec<Commit>,
+}
+
 pub fn compare_branches(
-    branch1: &str,
-    branch2: &str,
+    ref1: &str,
+    ref2: &str,
     words_to_exclude: Option<Vec<String>>,
     maybe_repo_path: Option<PathBuf>,
-) -> Result<Vec<Commit>, Box<dyn Error>> {
-    let repo_path = match get_repo_path(maybe_repo_path) {
-        Ok(repo_path) => repo_path,
-        Err(err) => return Err(err),
+) -> Result<Vec<Commit>, DiffError> {
+    // The entries of `DIFF_EXCLUDE` add to the given ones.
+    let mut exclude = options::env_excludes();
+    exclude.extend(words_to_exclude.unwrap_or_default());
+
+    let mut opts = CompareOptions::new().exclude(exclude);
+    if let Some(repo_path) = maybe_repo_path {
+        opts = opts.repo_path(repo_path);
+    }
+
+    compare_branches_with(ref1, ref2, &opts)
+}
+
+pub fn compare_branches_with(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let diff = compare_branches_both(ref1, ref2, opts)?;
+
+    Ok(diff.only_in_branch1)
+}
+
+pub fn compare_branches_both(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<BranchDiff, DiffError> {
+    compare_both(ref1, ref2, opts, None)
+}
+
+/// Like `compare_branches_both`, but reads the logs through `cache`, so comparing a ref again
+/// with the same options doesn't run `git log` for it. The patch ids and `Change-Id:` trailers
+/// are still read every time.
+pub fn compare_branches_both_cached(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+    cache: &CommitCache,
+) -> Result<BranchDiff, DiffError> {
+    compare_both(ref1, ref2, opts, Some(cache))
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(opts, cache), err))]
+fn compare_both(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+    cache: Option<&CommitCache>,
+) -> Result<BranchDiff, DiffError> {
+    let repo_path = prepare(None, &[ref1, ref2], RefCheck::Related, opts)?;
+    let filter = CommitFilter::new(&repo_path, opts)?;
+
+    let (only_in_branch1, only_in_branch2) = if opts.match_strategy == MatchStrategy::CherryMark {
+        cherry_marked_commits(&repo_path, ref1, ref2, opts)?
+    } else {
+        fetch_unique_commits(&repo_path, ref1, ref2, opts, cache)?
+    };
+
+    let unique = (only_in_branch1.len(), only_in_branch2.len());
+
+    let diff = BranchDiff {
+        only_in_branch1: narrow(only_in_branch1, &filter, opts),
+        only_in_branch2: narrow(only_in_branch2, &filter, opts),
+    };
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        filtered1 = unique.0 - diff.only_in_branch1.len(),
+        filtered2 = unique.1 - diff.only_in_branch2.len(),
+        only_in_branch1 = diff.only_in_branch1.len(),
+        only_in_branch2 = diff.only_in_branch2.len(),
+        "filtered the unique commits"
+    );
+
+    note(
+        opts,
+        format_args!(
+            "the filters left out {} of the {} commits unique to '{ref1}' and {} of the {} unique to '{ref2}'",
+            unique.0 - diff.only_in_branch1.len(),
+            unique.0,
+            unique.1 - diff.only_in_branch2.len(),
+            unique.1
+        ),
+    );
+
+    Ok(diff)
+}
+
+fn fetch_unique_commits(
+    repo_path: &str,
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+    cache: Option<&CommitCache>,
+) -> Result<(Vec<Commit>, Vec<Commit>), DiffError> {
+    let base = if opts.since_merge_base {
+        let base = merge_base(repo_path, ref1, ref2, opts)?;
+        if base.is_none() {
+            warn(
+                opts,
+                format_args!(
+                    "'{ref1}' and '{ref2}' have no merge base, so their whole histories were compared"
+                ),
+            );
+        }
+        base
+    } else {
+        None
+    };
+
+    let (revision1, revision2) = if opts.divergent_only {
+        (format!("{ref2}..{ref1}"), format!("{ref1}..{ref2}"))
+    } else if let Some(base) = &base {
+        (format!("{base}..{ref1}"), format!("{base}..{ref2}"))
+    } else {
+        (ref1.to_string(), ref2.to_string())
+    };
+
+    let (branch1, branch2) =
+        BranchLog::fetch_both(repo_path, &revision1, repo_path, &revision2, opts, cache)?;
+
+    // Without the shared history, an empty `ref2` only means it has nothing `ref1` lacks.
+    if !opts.divergent_only && base.is_none() {
+        ensure_not_empty(ref2, &branch2.commits, opts)?;
+    }
+
+    let squashes = if opts.detect_squashes {
+        Some((
+            squashed_summaries(&branch2.commits, opts),
+            squashed_summaries(&branch1.commits, opts),
+        ))
+    } else {
+        None
+    };
+
+    let (mut only_in_branch1, mut only_in_branch2) = unique_commits(branch1, branch2, opts)?;
+
+    if let Some((squashed_in_branch2, squashed_in_branch1)) = squashes {
+        mark_squashed(&mut only_in_branch1, &squashed_in_branch2, opts);
+        mark_squashed(&mut only_in_branch2, &squashed_in_branch1, opts);
+    }
+
+    Ok((only_in_branch1, only_in_branch2))
+}
+
+/// Lets `git log --cherry-mark --left-right ref1...ref2` match the commits the refs don't share,
+/// returning the ones it marks `<` and `>` rather than `=`.
+fn cherry_marked_commits(
+    repo_path: &str,
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<(Vec<Commit>, Vec<Commit>), DiffError> {
+    // The mark is read as the first extra placeholder, then taken out of them. `git log` only
+    // bounds the commit dates, so the author timestamp follows the mark to bound those here.
+    let by_author_date = reads_author_bounds(opts);
+    let mut extra_placeholders = vec!["%m".to_string()];
+    if by_author_date {
+        extra_placeholders.push("%at".to_string());
+    }
+    extra_placeholders.extend(opts.extra_placeholders.iter().cloned());
+    let mark_opts = CompareOptions {
+        extra_placeholders,
+        ..opts.clone()
     };
 
-    let raw_branch1_output = get_branch_commits(&repo_path, branch1).unwrap();
-    let raw_branch2_output = get_branch_commits(&repo_path, branch2).unwrap();
+    let revision = format!("{ref1}...{ref2}");
+    let output = read_log(
+        repo_path,
+        &revision,
+        &mark_opts,
+        &["--cherry-mark", "--left-right"],
+    )?;
+
+    let mut commits = parse_git_output(output, &mark_opts);
+    if by_author_date {
+        let bounds = date_bounds(repo_path, opts)?;
+        // The author timestamp is the second extra placeholder, after the mark.
+        commits.retain_mut(|commit| {
+            let timestamp = if commit.extra.len() > 1 {
+                Some(commit.extra.remove(1))
+            } else {
+                None
+            };
+            authored_between(timestamp.as_deref(), bounds)
+        });
+    }
+
+    opts.report(ProgressEvent::Fetched {
+        revision,
+        commits: commits.len(),
+    });
+    opts.report(ProgressEvent::Compared {
+        commits: commits.len(),
+    });
+
+    let mut only_in_branch1 = Vec::new();
+    let mut only_in_branch2 = Vec::new();
+
+    for mut commit in commits {
+        if commit.extra.is_empty() {
+            continue;
+        }
+
+        match commit.extra.remove(0).as_str() {
+            "<" => only_in_branch1.push(commit),
+            ">" => only_in_branch2.push(commit),
+            _ => {}
+        }
+    }
+
+    Ok((only_in_branch1, only_in_branch2))
+}
+
+/// A ref and the commit it resolved to, to tell what was compared when the ref moves, like
+/// `HEAD` during a rebase. Displayed as the ref, with the branch or commit `HEAD` is at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedRef {
+    /// The ref as it was given.
+    pub name: String,
+    /// The full hash of the commit.
+    pub hash: String,
+    /// The branch `HEAD` is on when the ref is `HEAD`, or `None` when it's detached or the ref
+    /// is anything else.
+    pub branch: Option<String>,
+    /// Whether the ref is `HEAD` and it's detached.
+    pub detached: bool,
+}
+
+impl fmt::Display for ResolvedRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.branch {
+            Some(branch) => write!(f, "{} ({branch})", self.name),
+            None if self.detached => {
+                let short_hash = self.hash.get(..7).unwrap_or(&self.hash);
+                write!(f, "{} (detached at {short_hash})", self.name)
+            }
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Resolves `ref_` to the commit it names, and, when it's `HEAD`, to the branch that's checked
+/// out or whether it's detached.
+pub fn resolve_ref(ref_: &str, opts: &CompareOptions) -> Result<ResolvedRef, DiffError> {
+    let repo_path = get_repo_path(opts.repo_path.as_deref(), opts)?;
+
+    resolve(&repo_path, ref_, opts)
+}
+
+/// The ref of a comparison a commit is unique to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The commit is only in `ref1`.
+    Left,
+    /// The commit is only in `ref2`.
+    Right,
+}
 
-    let raw_branch1_commits = parse_git_output(raw_branch1_output);
-    let raw_branch2_commits = parse_git_output(raw_branch2_output);
+/// Returns the commits unique to either ref in one list, each tagged with its side, sorted by
+/// date newest first unless `opts` sets a sort order.
+pub fn symmetric_diff(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<(Side, Commit)>, DiffError> {
+    let diff = compare_branches_both(ref1, ref2, opts)?;
 
-    let branch1_commits = raw_branch1_commits.into_iter().map(Commit::new).collect();
-    let branch2_commits = raw_branch2_commits.into_iter().map(Commit::new).collect();
+    let mut commits: Vec<(Side, Commit)> = diff
+        .only_in_branch1
+        .into_iter()
+        .map(|commit| (Side::Left, commit))
+        .chain(
+            diff.only_in_branch2
+                .into_iter()
+                .map(|commit| (Side::Right, commit)),
+        )
+        .collect();
 
-    let commits = exclude(subtract(branch1_commits, branch2_commits), words_to_exclude);
+    let sort_order = opts.sort_order.unwrap_or(SortOrder::Descending);
+    commits.sort_by(|(_, a), (_, b)| order_by_date(a, b, sort_order, opts));
 
     Ok(commits)
 }
 
-fn get_repo_path(repo_path: Option<PathBuf>) -> Result<String, Box<dyn Error>> {
-    let mut git_log_cmd = Command::new("git");
+/// Returns the commits in `branch` that aren't in its upstream, such as `origin/main` for `main`,
+/// failing with `DiffError::NoUpstream` if it has none.
+pub fn compare_with_upstream(
+    branch: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let repo_path = prepare(None, &[branch], RefCheck::Commits, opts)?;
 
-    if let Some(repo_path) = repo_path {
-        git_log_cmd.current_dir(repo_path);
+    let output = run(
+        git_command(opts)
+            .current_dir(&repo_path)
+            .args(["rev-parse", "--abbrev-ref"])
+            .arg(format!("{branch}@{{upstream}}")),
+        opts,
+    )?;
+
+    let upstream = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || upstream.is_empty() {
+        return Err(DiffError::NoUpstream(branch.to_string()));
     }
 
-    let git_top_level_output = git_log_cmd
-        .args(["rev-parse", "--show-toplevel"])
-        .output()?;
+    compare_branches_with(branch, &upstream, opts)
+}
+
+/// Returns how many commits `ref1` is ahead and behind `ref2`, that is the number of commits
+/// unique to each ref after the exclude entries are applied, so they match the commit lists.
+pub fn ahead_behind(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<(usize, usize), DiffError> {
+    let diff = compare_branches_both(ref1, ref2, opts)?;
 
-    if !git_top_level_output.status.success() {
-        return Err("Not inside a Git repository".into());
+    Ok((diff.only_in_branch1.len(), diff.only_in_branch2.len()))
+}
+
+/// Counts the commits in `ref1` that aren't in `ref2` with `git rev-list --count`, without
+/// reading them, such as to page through them. Unlike `compare_branches_with`, the commits are
+/// told apart by history whatever the match strategy, so cherry-picks are counted, and only the
+/// filters git applies itself are: `since`, `until`, `author`, `max_count`, `skip`, the merge
+/// filter, `first_parent` and `paths`. With `DateKind::Author`, `since` and `until` are ignored.
+pub fn count_unique(ref1: &str, ref2: &str, opts: &CompareOptions) -> Result<usize, DiffError> {
+    let repo_path = prepare(None, &[ref1, ref2], RefCheck::Commits, opts)?;
+
+    let mut cmd = git_command(opts);
+    cmd.current_dir(&repo_path)
+        .args(["rev-list", "--count"])
+        .arg(format!("{ref2}..{ref1}"));
+    add_log_filters(&mut cmd, opts);
+
+    let output = run(&mut cmd, opts)?;
+    if !output.status.success() {
+        return Err(git_error(&output));
     }
 
-    Ok(String::from_utf8_lossy(&git_top_level_output.stdout)
+    let count = String::from_utf8_lossy(&output.stdout);
+    count
         .trim()
-        .to_string())
+        .parse()
+        .map_err(|_| DiffError::Parse(format!("{:?} isn't a commit count", count.trim())))
 }
 
-fn exclude(mut commits: Vec<Commit>, exclusions: Option<Vec<String>>) -> Vec<Commit> {
-    let to_exclude = if let Some(words) = exclusions {
-        HashSet::from_iter(words)
-    } else {
-        HashSet::new()
+/// Whether a commit of `ref_` has `summary`, compared like summaries are matched, such as to
+/// check in a pre-push hook whether a fix is already on `main`. Git only lists the commits that
+/// mention the longest word of the summary, so the whole branch isn't read. The log filters of
+/// `opts` apply, except `since` and `until` with `DateKind::Author`, but nothing is excluded.
+pub fn summary_present_on(
+    ref_: &str,
+    summary: &str,
+    opts: &CompareOptions,
+) -> Result<bool, DiffError> {
+    let repo_path = prepare(None, &[ref_], RefCheck::Commits, opts)?;
+
+    let key = summary_key(summary, opts);
+    // Every word of the key is in the summaries it matches, however they're normalized.
+    let grep = key
+        .split_whitespace()
+        .max_by_key(|word| word.len())
+        .map(|word| format!("--grep={word}"));
+    let mut extra_args = vec!["--fixed-strings"];
+    extra_args.extend(grep.as_deref());
+
+    let commits = parse_git_output(read_log(&repo_path, ref_, opts, &extra_args)?, opts);
+
+    Ok(commits
+        .iter()
+        .any(|commit| summary_key(&commit.summary, opts) == key))
+}
+
+/// Returns the commits of the revision range `range1` that aren't in `range2`, such as
+/// `v1.0..v1.1` against `main..release`, matched with the strategy of `opts`. Any range
+/// `git log` takes as a single argument works, `A..B`, `A...B` or a plain ref. Since the ranges
+/// can leave out any history, `divergent_only` and `since_merge_base` are ignored, and an empty
+/// `range2` isn't an error.
+pub fn compare_ranges(
+    range1: &str,
+    range2: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let repo_path = prepare(None, &[range1, range2], RefCheck::Ranges, opts)?;
+    let filter = CommitFilter::new(&repo_path, opts)?;
+
+    let (range1, range2) =
+        BranchLog::fetch_both(&repo_path, range1, &repo_path, range2, opts, None)?;
+
+    let (only_in_range1, _) = unique_commits(range1, range2, opts)?;
+
+    Ok(narrow(only_in_range1, &filter, opts))
+}
+
+/// Returns the commits in `ref1` of the repository at `repo_path1` that aren't in `ref2` of the
+/// repository at `repo_path2`, such as a fork and its upstream cloned separately. The repository
+/// path of `opts` is ignored, and so is `divergent_only` since the refs share no history.
+pub fn compare_across_repos(
+    repo_path1: &Path,
+    ref1: &str,
+    repo_path2: &Path,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let repo_path1 = prepare(Some(repo_path1), &[ref1], RefCheck::Commits, opts)?;
+    let repo_path2 = prepare(Some(repo_path2), &[ref2], RefCheck::Commits, opts)?;
+    let filter = CommitFilter::new(&repo_path1, opts)?;
+
+    let (branch1, branch2) =
+        BranchLog::fetch_both(&repo_path1, ref1, &repo_path2, ref2, opts, None)?;
+    ensure_not_empty(ref2, &branch2.commits, opts)?;
+
+    let (only_in_branch1, _) = unique_commits(branch1, branch2, opts)?;
+
+    Ok(narrow(only_in_branch1, &filter, opts))
+}
+
+/// The commits of a revision, along with where they were read from.
+struct BranchLog<'a> {
+    repo_path: &'a str,
+    revision: &'a str,
+    commits: Vec<Commit>,
+}
+
+impl<'a> BranchLog<'a> {
+    fn fetch_both(
+        repo_path1: &'a str,
+        revision1: &'a str,
+        repo_path2: &'a str,
+        revision2: &'a str,
+        opts: &CompareOptions,
+        cache: Option<&CommitCache>,
+    ) -> Result<(Self, Self), DiffError> {
+        let (commits1, commits2) =
+            fetch_both_commits(repo_path1, revision1, repo_path2, revision2, opts, cache)?;
+
+        Ok((
+            Self {
+                repo_path: repo_path1,
+                revision: revision1,
+                commits: commits1,
+            },
+            Self {
+                repo_path: repo_path2,
+                revision: revision2,
+                commits: commits2,
+            },
+        ))
+    }
+}
+
+/// Matches the commits of both sides with the strategy of `opts`, returning the ones unique to
+/// each side.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip_all, fields(strategy = ?opts.match_strategy))
+)]
+fn unique_commits(
+    side1: BranchLog,
+    side2: BranchLog,
+    opts: &CompareOptions,
+) -> Result<(Vec<Commit>, Vec<Commit>), DiffError> {
+    let fetched = (side1.commits.len(), side2.commits.len());
+
+    // Both sides share one map since a hash always names the same commit.
+    let unique = match opts.match_strategy {
+        // A symmetric range can't span two repositories, so `git log --cherry-mark` is only used
+        // by `compare_branches_both`.
+        MatchStrategy::Summary | MatchStrategy::CherryMark => {
+            subtract_both(side1.commits, side2.commits, |commit| {
+                summary_key(&commit.summary, opts)
+            })
+        }
+        MatchStrategy::PatchId => {
+            let mut patch_ids = get_patch_ids(side1.repo_path, side1.revision, opts)?;
+            patch_ids.extend(get_patch_ids(side2.repo_path, side2.revision, opts)?);
+
+            subtract_both(side1.commits, side2.commits, |commit| {
+                patch_id_key(&patch_ids, commit, opts)
+            })
+        }
+        MatchStrategy::Fuzzy { threshold } => (
+            subtract_fuzzy(side1.commits.clone(), &side2.commits, threshold, opts),
+            subtract_fuzzy(side2.commits, &side1.commits, threshold, opts),
+        ),
+        MatchStrategy::ChangeId => {
+            let mut change_ids = get_change_ids(side1.repo_path, side1.revision, opts)?;
+            change_ids.extend(get_change_ids(side2.repo_path, side2.revision, opts)?);
+
+            subtract_both(side1.commits, side2.commits, |commit| {
+                change_id_key(&change_ids, commit, opts)
+            })
+        }
+        MatchStrategy::TreeHash => subtract_both(side1.commits, side2.commits, |commit| {
+            tree_key(commit, opts)
+        }),
     };
 
-    commits.retain(|commit| {
-        !to_exclude
-            .iter()
-            .any(|words_to_exclude| commit.summary.contains(words_to_exclude))
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        matched1 = fetched.0 - unique.0.len(),
+        matched2 = fetched.1 - unique.1.len(),
+        "matched the commits"
+    );
+
+    opts.report(ProgressEvent::Compared {
+        commits: fetched.0 + fetched.1,
     });
 
-    commits
+    Ok(unique)
 }
 
-fn subtract(commits1: Vec<Commit>, commits2: Vec<Commit>) -> Vec<Commit> {
-    let hash = commits2.iter().fold(HashSet::new(), |mut hash, commit| {
-        hash.insert(commit.summary.to_string());
-        hash
-    });
+/// Returns the commits of `commits1` whose summary isn't in `commits2`, leaving out the ones whose
+/// summary contains any of `words_to_exclude`. Paired with `get_branch_commits` and
+/// `parse_git_output`, this compares already fetched commits in either direction without running
+/// git again.
+pub fn compare_parsed(
+    commits1: Vec<Commit>,
+    commits2: &[Commit],
+    words_to_exclude: &[String],
+) -> Vec<Commit> {
+    let filter = CommitFilter {
+        exclusions: Exclusions::substrings(words_to_exclude, false),
+        inclusions: None,
+        excluded_authors: Vec::new(),
+        merge_summaries: None,
+        min_files: None,
+        min_changed_lines: None,
+        redactions: Vec::new(),
+        pseudonyms: Pseudonyms::default(),
+    };
+
+    filter_commits(
+        subtract(commits1, commits2, |commit| commit.summary.clone()),
+        &filter,
+    )
+}
 
-    let mut commits = Vec::new();
+/// Returns the commits of `commits1` that aren't in `commits2`, matched, filtered and sorted
+/// like `compare_branches_with` does, without running git, such as for commits read some other
+/// way. The strategies that ask git, `PatchId`, `ChangeId` and `CherryMark`, fall back to the
+/// summary, and `exclude_mine` is ignored since the current user is read from git.
+pub fn diff_commits(
+    commits1: &[Commit],
+    commits2: &[Commit],
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let filter_opts = CompareOptions {
+        exclude_mine: false,
+        ..opts.clone()
+    };
+    // Without `exclude_mine`, the repository isn't read.
+    let filter = CommitFilter::new("", &filter_opts)?;
 
-    for commit in commits1 {
-        if !hash.contains(&commit.summary) {
-            commits.push(commit);
+    let commits1 = commits1.to_vec();
+    let only_in_commits1 = match opts.match_strategy {
+        MatchStrategy::Fuzzy { threshold } => subtract_fuzzy(commits1, commits2, threshold, opts),
+        MatchStrategy::TreeHash => subtract(commits1, commits2, |commit| tree_key(commit, opts)),
+        MatchStrategy::Summary
+        | MatchStrategy::PatchId
+        | MatchStrategy::ChangeId
+        | MatchStrategy::CherryMark => subtract(commits1, commits2, |commit| {
+            summary_key(&commit.summary, opts)
+        }),
+    };
+
+    Ok(narrow(only_in_commits1, &filter, opts))
+}
+
+/// Returns the commits in `target` whose summary is in none of the `others`, such as the commits
+/// of a dev branch missing from both staging and prod. Commits are matched by summary whatever
+/// the match strategy of `opts`.
+pub fn commits_unique_to(
+    target: &str,
+    others: &[&str],
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let mut refs = vec![target];
+    refs.extend_from_slice(others);
+    let repo_path = prepare(None, &refs, RefCheck::Related, opts)?;
+
+    unique_to(&repo_path, target, others, opts, true)
+}
+
+/// Returns the commits in `branch` whose summary is on none of the remote-tracking branches,
+/// such as to check nothing would be lost before deleting or force-pushing it. Unlike with
+/// `commits_unique_to`, remote branches with unrelated histories or without commits left by
+/// the log filters are compared too, rather than failing. With `fetch_first`, every remote is
+/// fetched before its branches are listed.
+pub fn commits_not_on_any_remote(
+    branch: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let repo_path = prepare(None, &[branch], RefCheck::Commits, opts)?;
+
+    if opts.fetch_first {
+        let output = run(
+            git_command(opts)
+                .current_dir(&repo_path)
+                .args(["fetch", "--quiet", "--all"]),
+            opts,
+        )?;
+        if !output.status.success() {
+            return Err(git_error(&output));
         }
     }
 
-    commits
-}
+    let remote_branches = remote_branches(&repo_path, opts)?;
+    let remote_branches: Vec<&str> = remote_branches.iter().map(String::as_str).collect();
 
-fn get_branch_commits(repo_path: &str, branch: &str) -> Result<Output, std::io::Error> {
-    Command::new("git")
-        .current_dir(repo_path)
-        .args([
-            "log",
-            branch,
-            "--pretty=format:%h|%ad|%s",
-            "--date=format:%Y-%m-%d",
-        ])
-        .output()
+    unique_to(&repo_path, branch, &remote_branches, opts, false)
 }
 
-fn parse_git_output(raw_commits: Output) -> Vec<String> {
-    let git_log_output_str = String::from_utf8_lossy(&raw_commits.stdout);
-    git_log_output_str
+/// The full names of the remote-tracking branches, leaving out the `HEAD` of each remote, which
+/// points at one of them.
+fn remote_branches(repo_path: &str, opts: &CompareOptions) -> Result<Vec<String>, DiffError> {
+    let output = run(
+        git_command(opts).current_dir(repo_path).args([
+            "for-each-ref",
+            "--format=%(refname)",
+            "refs/remotes",
+        ]),
+        opts,
+    )?;
+    if !output.status.success() {
+        return Err(git_error(&output));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
         .lines()
-        .map(String::from)
-        .collect::<Vec<String>>()
+        .filter(|ref_| !ref_.ends_with("/HEAD"))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Returns the commits in `target` whose summary is in none of the `others`, all of them
+/// resolving to commits. With `strict`, the `others` have to have commits left by the log
+/// filters.
+fn unique_to(
+    repo_path: &str,
+    target: &str,
+    others: &[&str],
+    opts: &CompareOptions,
+    strict: bool,
+) -> Result<Vec<Commit>, DiffError> {
+    let filter = CommitFilter::new(repo_path, opts)?;
+
+    let mut summaries = HashSet::new();
+    for other in others {
+        let commits = fetch_commits(repo_path, other, opts, None)?;
+        if strict {
+            ensure_not_empty(other, &commits, opts)?;
+        }
+
+        summaries.extend(
+            commits
+                .iter()
+                .map(|commit| summary_key(&commit.summary, opts)),
+        );
+    }
+
+    let mut only_in_target = fetch_commits(repo_path, target, opts, None)?;
+    only_in_target.retain(|commit| !summaries.contains(&summary_key(&commit.summary, opts)));
+
+    Ok(narrow(only_in_target, &filter, opts))
+}
+
+/// Returns every commit of `source` with the `targets` that have a commit with its summary, in
+/// the order they were given, such as to see which release branches a fix was backported to.
+/// Commits are matched by summary whatever the match strategy of `opts`, and the exclude entries
+/// and other filters only apply to `source`.
+pub fn commit_coverage(
+    source: &str,
+    targets: &[&str],
+    opts: &CompareOptions,
+) -> Result<Vec<(Commit, Vec<String>)>, DiffError> {
+    let mut refs = vec![source];
+    refs.extend_from_slice(targets);
+    let repo_path = prepare(None, &refs, RefCheck::Related, opts)?;
+    let filter = CommitFilter::new(&repo_path, opts)?;
+
+    let mut target_summaries = Vec::new();
+    for target in targets {
+        let commits = fetch_commits(&repo_path, target, opts, None)?;
+        ensure_not_empty(target, &commits, opts)?;
+
+        let summaries: HashSet<String> = commits
+            .iter()
+            .map(|commit| summary_key(&commit.summary, opts))
+            .collect();
+        target_summaries.push((target, summaries));
+    }
+
+    // Keyed by hash, since narrowing can drop, reorder, anonymize and redact the commits.
+    let commits = fetch_commits(&repo_path, source, opts, None)?;
+    let coverage: HashMap<String, Vec<String>> = commits
+        .iter()
+        .map(|commit| {
+            let key = summary_key(&commit.summary, opts);
+            let covered_by = target_summaries
+                .iter()
+                .filter(|(_, summaries)| summaries.contains(&key))
+                .map(|(target, _)| target.to_string())
+                .collect();
+            (commit.hash.clone(), covered_by)
+        })
+        .collect();
+
+    Ok(narrow(commits, &filter, opts)
+        .into_iter()
+        .map(|commit| {
+            let covered_by = coverage.get(&commit.hash).cloned().unwrap_or_default();
+            (commit, covered_by)
+        })
+        .collect())
+}
+
+/// Returns the commits in `ref1` that aren't in `ref2`, where two commits match when `key_fn`
+/// returns the same key for them. This replaces the match strategy of `opts`.
+pub fn compare_branches_by<F>(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+    key_fn: F,
+) -> Result<Vec<Commit>, DiffError>
+where
+    F: Fn(&Commit) -> String,
+{
+    let repo_path = prepare(None, &[ref1, ref2], RefCheck::Related, opts)?;
+    let filter = CommitFilter::new(&repo_path, opts)?;
+
+    let (branch1_commits, branch2_commits) =
+        fetch_both_commits(&repo_path, ref1, &repo_path, ref2, opts, None)?;
+    ensure_not_empty(ref2, &branch2_commits, opts)?;
+
+    let only_in_branch1 = subtract(branch1_commits, &branch2_commits, key_fn);
+
+    Ok(narrow(only_in_branch1, &filter, opts))
+}
+
+/// Yields the commits in `ref1` that aren't in `ref2` while `git log` lists them, so stopping
+/// early doesn't read the whole history of `ref1`. Only `ref2` is read up front. Commits are
+/// matched by summary whatever the match strategy of `opts`, they come newest first whatever the
+/// sort order, and the timeout doesn't apply to reading `ref1`.
+pub fn compare_branches_iter(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<impl Iterator<Item = Result<Commit, DiffError>>, DiffError> {
+    let repo_path = prepare(None, &[ref1, ref2], RefCheck::Related, opts)?;
+    let filter = CommitFilter::new(&repo_path, opts)?;
+
+    let branch2_commits = fetch_commits(&repo_path, ref2, opts, None)?;
+    ensure_not_empty(ref2, &branch2_commits, opts)?;
+
+    let branch2_summaries: HashSet<String> = branch2_commits
+        .iter()
+        .map(|commit| summary_key(&commit.summary, opts))
+        .collect();
+    let squashed_in_branch2 = if opts.detect_squashes {
+        Some(squashed_summaries(&branch2_commits, opts))
+    } else {
+        None
+    };
+
+    // Like `read_commits`, the author dates are bounded here rather than by `git log`, from the
+    // author timestamp read as the first extra placeholder.
+    let author_bounds = if reads_author_bounds(opts) {
+        Some(date_bounds(&repo_path, opts)?)
+    } else {
+        None
+    };
+    let mut stream_opts = opts.clone();
+    if author_bounds.is_some() {
+        stream_opts.since = None;
+        stream_opts.until = None;
+        stream_opts.extra_placeholders.insert(0, "%at".to_string());
+    }
+    let branch1_commits = LogStream::spawn(&repo_path, ref1, &stream_opts)?;
+    let opts = opts.clone();
+    let pseudonyms = Pseudonyms::default();
+
+    Ok(branch1_commits.filter_map(move |commit| {
+        let mut commit = match commit {
+            Ok(commit) => commit,
+            Err(err) => return Some(Err(err)),
+        };
+
+        if let Some(bounds) = author_bounds {
+            let timestamp = if commit.extra.is_empty() {
+                None
+            } else {
+                Some(commit.extra.remove(0))
+            };
+            if !authored_between(timestamp.as_deref(), bounds) {
+                return None;
+            }
+        }
+
+        let keeps = !branch2_summaries.contains(&summary_key(&commit.summary, &opts))
+            && filter.keeps(&commit)
+            && (opts.include_types.is_empty() || has_type(&commit, &opts.include_types))
+            && opts
+                .body_contains
+                .as_deref()
+                .is_none_or(|text| has_in_body(&commit, text));
+        if !keeps {
+            return None;
+        }
+
+        if let Some(squashed) = &squashed_in_branch2 {
+            mark_squashed(std::slice::from_mut(&mut commit), squashed, &opts);
+        }
+        finish(
+            std::slice::from_mut(&mut commit),
+            &filter.redactions,
+            &pseudonyms,
+            &opts,
+        );
+
+        Some(Ok(commit))
+    }))
+}
+
+/// The commits of a running `git log`, parsed as its lines are read.
+struct LogStream {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+    layout: Layout,
+    // With `--numstat`, a commit is only complete once the lines that follow it are read.
+    pending: Option<Commit>,
+    chunks: usize,
+    done: bool,
+}
+
+impl LogStream {
+    fn spawn(repo_path: &str, revision: &str, opts: &CompareOptions) -> Result<Self, DiffError> {
+        ensure_followable(opts)?;
+        ensure_separators(opts)?;
+
+        let mut log_cmd = log_command(repo_path, revision, opts);
+        print_command(&log_cmd, opts);
+
+        let mut child = log_cmd
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(spawn_error)?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| io::Error::other("Failed to read the git log output"))?;
+
+        Ok(Self {
+            child,
+            stdout: BufReader::new(stdout),
+            layout: Layout::new(opts),
+            pending: None,
+            chunks: 0,
+            done: false,
+        })
+    }
+
+    fn finish(&mut self) -> Result<(), DiffError> {
+        self.done = true;
+
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            pipe.read_to_string(&mut stderr)?;
+        }
+
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(DiffError::GitFailed {
+                code: status.code(),
+                stderr: stderr.trim().to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for LogStream {
+    type Item = Result<Commit, DiffError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        // `ensure_separators` only lets ASCII separators through.
+        let separator = self.layout.record_separator as u8;
+        let mut chunk = Vec::new();
+        loop {
+            chunk.clear();
+            match self.stdout.read_until(separator, &mut chunk) {
+                Ok(0) => {
+                    return match self.finish() {
+                        Ok(()) => self.pending.take().map(Ok),
+                        Err(err) => Some(Err(err)),
+                    };
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+            }
+
+            if chunk.last() == Some(&separator) {
+                chunk.pop();
+            }
+            let chunk = String::from_utf8_lossy(&chunk);
+
+            // Like in `parse_git_output`, nothing comes before the first commit, then the chunks
+            // alternate between a commit and the lines that follow it.
+            self.chunks += 1;
+            if self.chunks == 1 {
+                continue;
+            }
+
+            if self.chunks.is_multiple_of(2) {
+                self.pending = parse_record(&chunk, &self.layout);
+            } else if let Some(mut commit) = self.pending.take() {
+                chunk
+                    .lines()
+                    .filter_map(parse_numstat_line)
+                    .for_each(|numstat| add_file(&mut commit, numstat));
+                return Some(Ok(commit));
+            }
+        }
+    }
+}
+
+impl Drop for LogStream {
+    fn drop(&mut self) {
+        // Stopping early leaves git running, so it's killed rather than left to fill the pipe.
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// The commits unique to `ref1`, plus the ones whose summary is on both branches but with
+/// different dates, as happens with cherry-picks.
+#[derive(Debug)]
+pub struct DatedDiff {
+    pub only_in_branch1: Vec<Commit>,
+    /// Pairs of the `ref1` commit and the `ref2` commit with the same summary.
+    pub date_mismatches: Vec<(Commit, Commit)>,
+}
+
+/// Compares the refs by summary, whatever the match strategy of `opts`.
+pub fn compare_branches_with_date_mismatches(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<DatedDiff, DiffError> {
+    let repo_path = prepare(None, &[ref1, ref2], RefCheck::Related, opts)?;
+    let filter = CommitFilter::new(&repo_path, opts)?;
+
+    let (branch1_commits, branch2_commits) =
+        fetch_both_commits(&repo_path, ref1, &repo_path, ref2, opts, None)?;
+    ensure_not_empty(ref2, &branch2_commits, opts)?;
+
+    let (only_in_branch1, matched) = partition_matched(
+        filter_commits(branch1_commits, &filter),
+        &branch2_commits,
+        |commit| summary_key(&commit.summary, opts),
+    );
+    let mut date_mismatches: Vec<(Commit, Commit)> = matched
+        .into_iter()
+        .filter(|(commit1, commit2)| commit1.author_date != commit2.author_date)
+        .collect();
+
+    let only_in_branch1 = body_contains(
+        include_types(only_in_branch1, &opts.include_types),
+        opts.body_contains.as_deref(),
+    );
+    let mut only_in_branch1 = sort(collapse_reverts(only_in_branch1, opts.revert_depth), opts);
+    finish(
+        &mut only_in_branch1,
+        &filter.redactions,
+        &filter.pseudonyms,
+        opts,
+    );
+    finish_pairs(&mut date_mismatches, &filter, opts);
+
+    Ok(DatedDiff {
+        only_in_branch1,
+        date_mismatches,
+    })
+}
+
+/// Returns every `ref1` commit whose summary is on `ref2`, paired with the newest `ref2` commit
+/// with that summary, newest first, so their hashes and dates can be compared. Commits are
+/// matched by summary whatever the match strategy of `opts`.
+pub fn matched_commits(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<(Commit, Commit)>, DiffError> {
+    let repo_path = prepare(None, &[ref1, ref2], RefCheck::Related, opts)?;
+    let filter = CommitFilter::new(&repo_path, opts)?;
+
+    let (branch1_commits, branch2_commits) =
+        fetch_both_commits(&repo_path, ref1, &repo_path, ref2, opts, None)?;
+    ensure_not_empty(ref2, &branch2_commits, opts)?;
+
+    let (_, mut matched) = partition_matched(
+        filter_commits(branch1_commits, &filter),
+        &branch2_commits,
+        |commit| summary_key(&commit.summary, opts),
+    );
+    finish_pairs(&mut matched, &filter, opts);
+
+    Ok(matched)
+}
+
+/// Returns the pairs of `matched_commits` whose hashes differ, the commits with the same summary
+/// on both refs that aren't shared history but likely cherry-picked or rebased.
+pub fn rewritten_commits(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<(Commit, Commit)>, DiffError> {
+    let mut matched = matched_commits(ref1, ref2, opts)?;
+    matched.retain(|(commit1, commit2)| commit1.hash != commit2.hash);
+
+    Ok(matched)
+}
+
+/// Like `matched_commits`, but matches the commits with the strategy of `opts`, so a
+/// cherry-pick whose summary was reworded is still paired with its original by `PatchId`.
+/// `CherryMark` pairs the commits by patch id too. With `Fuzzy`, a commit is paired with the
+/// newest similar enough commit.
+pub fn common_commits(
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<(Commit, Commit)>, DiffError> {
+    let repo_path = prepare(None, &[ref1, ref2], RefCheck::Related, opts)?;
+    let filter = CommitFilter::new(&repo_path, opts)?;
+
+    let (branch1_commits, branch2_commits) =
+        fetch_both_commits(&repo_path, ref1, &repo_path, ref2, opts, None)?;
+    ensure_not_empty(ref2, &branch2_commits, opts)?;
+
+    let branch1_commits = filter_commits(branch1_commits, &filter);
+
+    let mut matched = match opts.match_strategy {
+        MatchStrategy::Summary => {
+            partition_matched(branch1_commits, &branch2_commits, |commit| {
+                summary_key(&commit.summary, opts)
+            })
+            .1
+        }
+        MatchStrategy::PatchId | MatchStrategy::CherryMark => {
+            let mut patch_ids = get_patch_ids(&repo_path, ref1, opts)?;
+            patch_ids.extend(get_patch_ids(&repo_path, ref2, opts)?);
+
+            partition_matched(branch1_commits, &branch2_commits, |commit| {
+                patch_id_key(&patch_ids, commit, opts)
+            })
+            .1
+        }
+        MatchStrategy::ChangeId => {
+            let mut change_ids = get_change_ids(&repo_path, ref1, opts)?;
+            change_ids.extend(get_change_ids(&repo_path, ref2, opts)?);
+
+            partition_matched(branch1_commits, &branch2_commits, |commit| {
+                change_id_key(&change_ids, commit, opts)
+            })
+            .1
+        }
+        MatchStrategy::TreeHash => {
+            partition_matched(branch1_commits, &branch2_commits, |commit| {
+                tree_key(commit, opts)
+            })
+            .1
+        }
+        MatchStrategy::Fuzzy { threshold } => {
+            pair_fuzzy(branch1_commits, &branch2_commits, threshold, opts)
+        }
+    };
+    finish_pairs(&mut matched, &filter, opts);
+
+    Ok(matched)
+}
+
+/// Runs the checks a comparison of the refs starts with, without reading their logs: the
+/// repository path exists and is a repository, the exclude, include and merge summary patterns
+/// compile, the fuzzy threshold is in range, both refs resolve to commits and they share
+/// history, `follow` has one path, and the working tree is clean with `require_clean`.
+/// None of the remotes are fetched, and the paths aren't checked since they can name files that
+/// were since deleted.
+pub fn validate(ref1: &str, ref2: &str, opts: &CompareOptions) -> Result<(), DiffError> {
+    let unfetched_opts = CompareOptions {
+        fetch_first: false,
+        ..opts.clone()
+    };
+    let repo_path = prepare(None, &[ref1, ref2], RefCheck::Related, &unfetched_opts)?;
+    CommitFilter::new(&repo_path, opts)?;
+    ensure_followable(opts)?;
+
+    Ok(())
+}
+
+/// How `prepare` checks the refs of a comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RefCheck {
+    /// Every ref resolves to a commit.
+    Commits,
+    /// Every ref resolves to a commit, and the ones after the first share history with it.
+    Related,
+    /// Every ref is a revision range or resolves to a commit.
+    Ranges,
+}
+
+/// Starts every entry point that reads `refs`: finds the repository at `repo_path`, or the one
+/// of `opts` without it, fetches the remotes of the refs with `fetch_first`, and checks the refs
+/// as `check` says and, when one is `HEAD`, that it isn't detached with `reject_detached_head`.
+fn prepare(
+    repo_path: Option<&Path>,
+    refs: &[&str],
+    check: RefCheck,
+    opts: &CompareOptions,
+) -> Result<String, DiffError> {
+    ensure_threshold(opts)?;
+    let repo_path = get_repo_path(repo_path.or(opts.repo_path.as_deref()), opts)?;
+
+    fetch_remotes(&repo_path, refs, opts)?;
+    for ref_ in refs {
+        match check {
+            RefCheck::Ranges => verify_range(&repo_path, ref_, opts)?,
+            RefCheck::Commits | RefCheck::Related => verify_ref(&repo_path, ref_, opts)?,
+        }
+        check_head(&repo_path, ref_, opts)?;
+    }
+
+    if let (RefCheck::Related, Some((first, rest))) = (check, refs.split_first()) {
+        for ref_ in rest {
+            ensure_related(&repo_path, first, ref_, opts)?;
+        }
+    }
+
+    Ok(repo_path)
+}
+
+fn get_repo_path(repo_path: Option<&Path>, opts: &CompareOptions) -> Result<String, DiffError> {
+    // Git would otherwise fail to start with a bare "No such file or directory".
+    if let Some(repo_path) = repo_path {
+        if !repo_path.is_dir() {
+            return Err(DiffError::InvalidRepoPath(repo_path.to_path_buf()));
+        }
+    }
+
+    // Git keeps running in the given directory rather than the top level, so the paths to
+    // compare are relative to it, as with `git log`. Subdirectories and linked worktrees work
+    // the same: branches and tags are shared by every worktree of a repository, while `HEAD` is
+    // the one of the worktree the directory belongs to.
+    let repo_path = repo_path.unwrap_or(Path::new("."));
+    ensure_repo(repo_path, opts)?;
+
+    let repo_path = repo_path.to_string_lossy().into_owned();
+    ensure_clean(&repo_path, opts)?;
+
+    Ok(repo_path)
+}
+
+/// With the `require_clean` option, fails if `git status` lists changes to tracked files.
+fn ensure_clean(repo_path: &str, opts: &CompareOptions) -> Result<(), DiffError> {
+    if !opts.require_clean {
+        return Ok(());
+    }
+
+    let output = run(
+        // Without `--no-optional-locks`, `git status` takes the index lock to refresh it.
+        git_command(opts).current_dir(repo_path).args([
+            "--no-optional-locks",
+            "status",
+            "--porcelain",
+            "--untracked-files=no",
+        ]),
+        opts,
+    )?;
+    if !output.status.success() {
+        return Err(git_error(&output));
+    }
+
+    let changes = String::from_utf8_lossy(&output.stdout).lines().count();
+    if changes > 0 {
+        return Err(DiffError::DirtyWorkingTree(changes));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "git2")]
+fn ensure_repo(repo_path: &Path, _opts: &CompareOptions) -> Result<(), DiffError> {
+    revwalk::ensure_repo(repo_path)
+}
+
+#[cfg(not(feature = "git2"))]
+fn ensure_repo(repo_path: &Path, opts: &CompareOptions) -> Result<(), DiffError> {
+    let git_top_level_output = run(
+        git_command(opts)
+            .current_dir(repo_path)
+            .args(["rev-parse", "--show-toplevel"]),
+        opts,
+    )?;
+
+    if !git_top_level_output.status.success() {
+        return Err(DiffError::NotARepo);
+    }
+
+    Ok(())
+}
+
+enum Exclusions {
+    Substrings {
+        words: HashSet<String>,
+        case_insensitive: bool,
+    },
+    Patterns(Vec<Regex>),
+}
+
+impl Exclusions {
+    fn new(
+        words: &[String],
+        mode: ExcludeMode,
+        case_insensitive: bool,
+        whole_word: bool,
+    ) -> Result<Self, DiffError> {
+        let words = non_blank(words);
+
+        match mode {
+            ExcludeMode::Substring if whole_word => {
+                let patterns: Vec<String> = words
+                    .iter()
+                    .map(|word| format!(r"(?:^|\W){}(?:\W|$)", regex::escape(word)))
+                    .collect();
+
+                Self::new(&patterns, ExcludeMode::Regex, case_insensitive, false)
+            }
+            ExcludeMode::Substring => Ok(Self::substrings(&words, case_insensitive)),
+            ExcludeMode::Regex => {
+                let patterns = words
+                    .iter()
+                    .map(|word| {
+                        RegexBuilder::new(word)
+                            .case_insensitive(case_insensitive)
+                            .build()
+                            .map_err(|source| DiffError::InvalidPattern {
+                                pattern: word.to_string(),
+                                source,
+                            })
+                    })
+                    .collect::<Result<Vec<Regex>, DiffError>>()?;
+
+                Ok(Self::Patterns(patterns))
+            }
+        }
+    }
+
+    fn matches(&self, summary: &str) -> bool {
+        match self {
+            Self::Substrings {
+                words,
+                case_insensitive,
+            } => {
+                let summary = if *case_insensitive {
+                    summary.to_lowercase()
+                } else {
+                    summary.to_string()
+                };
+
+                words.iter().any(|word| summary.contains(word))
+            }
+            Self::Patterns(patterns) => patterns.iter().any(|pattern| pattern.is_match(summary)),
+        }
+    }
+
+    /// Matches the summaries containing any of `words`, which unlike patterns can't fail to build.
+    fn substrings(words: &[String], case_insensitive: bool) -> Self {
+        let words = non_blank(words);
+        let words = if case_insensitive {
+            words.iter().map(|word| word.to_lowercase()).collect()
+        } else {
+            words.into_iter().collect()
+        };
+
+        Self::Substrings {
+            words,
+            case_insensitive,
+        }
+    }
+}
+
+// An empty entry is in every summary, so a stray blank line would leave out every commit.
+fn non_blank(words: &[String]) -> Vec<String> {
+    words
+        .iter()
+        .filter(|word| !word.trim().is_empty())
+        .cloned()
+        .collect()
+}
+
+/// The exclude and include entries of the options, matched the same way, and the excluded
+/// authors.
+struct CommitFilter {
+    exclusions: Exclusions,
+    /// `None` when there are no include entries, so every summary is included.
+    inclusions: Option<Exclusions>,
+    /// Lowercased, since they are matched ignoring case.
+    excluded_authors: Vec<String>,
+    /// `None` unless the merge summaries are skipped.
+    merge_summaries: Option<Exclusions>,
+    min_files: Option<usize>,
+    min_changed_lines: Option<usize>,
+    /// The patterns masked in the returned commits, not used to filter them.
+    redactions: Vec<Regex>,
+    /// The pseudonyms given to the authors of the returned commits with `anonymize_authors`.
+    pseudonyms: Pseudonyms,
+}
+
+impl CommitFilter {
+    /// `repo_path` is the repository whose `user.email` is excluded with `exclude_mine`.
+    fn new(repo_path: &str, opts: &CompareOptions) -> Result<Self, DiffError> {
+        let exclusions = Exclusions::new(
+            &opts.exclude,
+            opts.exclude_mode,
+            opts.case_insensitive,
+            opts.whole_word,
+        )?;
+        let inclusions = if opts.include.iter().all(|word| word.trim().is_empty()) {
+            None
+        } else {
+            Some(Exclusions::new(
+                &opts.include,
+                opts.exclude_mode,
+                opts.case_insensitive,
+                opts.whole_word,
+            )?)
+        };
+
+        let mut excluded_authors: Vec<String> = opts
+            .exclude_authors
+            .iter()
+            .map(|author| author.to_lowercase())
+            .collect();
+        if opts.exclude_mine {
+            excluded_authors.push(user_email(repo_path, opts)?.to_lowercase());
+        }
+
+        let merge_summaries = opts
+            .merge_summary_patterns
+            .as_deref()
+            .map(|patterns| Exclusions::new(patterns, ExcludeMode::Regex, false, false))
+            .transpose()?;
+
+        Ok(Self {
+            exclusions,
+            inclusions,
+            excluded_authors,
+            merge_summaries,
+            min_files: opts.min_files,
+            min_changed_lines: opts.min_changed_lines,
+            redactions: redactions(opts)?,
+            pseudonyms: Pseudonyms::default(),
+        })
+    }
+
+    /// Whether the summary of `commit` matches no exclude entry and, if there are any, an include
+    /// entry, its author isn't excluded, it doesn't look like a merge summary if those are
+    /// skipped, and it changes enough.
+    fn keeps(&self, commit: &Commit) -> bool {
+        !self.exclusions.matches(&commit.summary)
+            && self
+                .inclusions
+                .as_ref()
+                .is_none_or(|inclusions| inclusions.matches(&commit.summary))
+            && !self.excludes_author(commit)
+            && !self
+                .merge_summaries
+                .as_ref()
+                .is_some_and(|merge_summaries| merge_summaries.matches(&commit.summary))
+            && self.is_large_enough(commit)
+    }
+
+    // Without a diff, there is nothing to measure.
+    fn is_large_enough(&self, commit: &Commit) -> bool {
+        commit.files.is_empty()
+            || (self
+                .min_files
+                .is_none_or(|min_files| commit.files.len() >= min_files)
+                && self.min_changed_lines.is_none_or(|min_changed_lines| {
+                    commit.additions + commit.deletions >= min_changed_lines
+                }))
+    }
+
+    fn excludes_author(&self, commit: &Commit) -> bool {
+        let name = commit.author.to_lowercase();
+        let email = commit.email.as_deref().map(str::to_lowercase);
+
+        self.excluded_authors
+            .iter()
+            .any(|author| *author == name || email.as_ref() == Some(author))
+    }
+}
+
+fn user_email(repo_path: &str, opts: &CompareOptions) -> Result<String, DiffError> {
+    let output = run(
+        git_command(opts)
+            .current_dir(repo_path)
+            .args(["config", "user.email"]),
+        opts,
+    )?;
+
+    // `git config` exits with 1 when the key isn't set.
+    if output.status.code() == Some(1) {
+        return Err(DiffError::UserEmailNotSet);
+    }
+    if !output.status.success() {
+        return Err(git_error(&output));
+    }
+
+    let email = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if email.is_empty() {
+        return Err(DiffError::UserEmailNotSet);
+    }
+
+    Ok(email)
+}
+
+fn filter_commits(mut commits: Vec<Commit>, filter: &CommitFilter) -> Vec<Commit> {
+    commits.retain(|commit| filter.keeps(commit));
+
+    commits
+}
+
+/// Applies the filters of `opts` that only narrow down the unique commits, then sorts them.
+// The excluded commits go first, so they can't cancel a revert or win over a duplicate.
+fn narrow(commits: Vec<Commit>, filter: &CommitFilter, opts: &CompareOptions) -> Vec<Commit> {
+    let commits = include_types(filter_commits(commits, filter), &opts.include_types);
+    let commits = body_contains(commits, opts.body_contains.as_deref());
+    let commits = collapse_reverts(commits, opts.revert_depth);
+    let commits = if opts.dedupe {
+        dedupe(commits, opts)
+    } else {
+        commits
+    };
+
+    let mut commits = sort(commits, opts);
+    finish(&mut commits, &filter.redactions, &filter.pseudonyms, opts);
+
+    commits
+}
+
+/// The pseudonyms of the authors anonymized in one comparison, numbered from 1 in the order
+/// they were seen. Authors are told apart by their email, so namesakes get pseudonyms of their
+/// own, or by their name when the email wasn't read.
+#[derive(Debug, Default)]
+struct Pseudonyms(RefCell<BTreeMap<String, usize>>);
+
+impl Pseudonyms {
+    fn number(&self, commit: &Commit) -> usize {
+        let key = match &commit.email {
+            Some(email) => email.to_lowercase(),
+            None => commit.author.clone(),
+        };
+
+        let mut numbers = self.0.borrow_mut();
+        let next = numbers.len() + 1;
+        *numbers.entry(key).or_insert(next)
+    }
+}
+
+fn anonymize(commits: &mut [Commit], pseudonyms: &Pseudonyms, opts: &CompareOptions) {
+    if !opts.anonymize_authors {
+        return;
+    }
+
+    for commit in commits {
+        commit.author = format!("author-{}", pseudonyms.number(commit));
+        commit.email = None;
+    }
+}
+
+fn redactions(opts: &CompareOptions) -> Result<Vec<Regex>, DiffError> {
+    opts.redaction_patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|source| DiffError::InvalidPattern {
+                pattern: pattern.to_string(),
+                source,
+            })
+        })
+        .collect()
+}
+
+fn redact(commits: &mut [Commit], redactions: &[Regex]) {
+    for commit in commits {
+        for redaction in redactions {
+            commit.summary = redaction.replace_all(&commit.summary, "***").into_owned();
+            if let Some(body) = &commit.body {
+                commit.body = Some(redaction.replace_all(body, "***").into_owned());
+            }
+        }
+    }
+}
+
+/// Anonymizes and redacts the commits about to be returned, which only changes how they read.
+fn finish(
+    commits: &mut [Commit],
+    redactions: &[Regex],
+    pseudonyms: &Pseudonyms,
+    opts: &CompareOptions,
+) {
+    anonymize(commits, pseudonyms, opts);
+    redact(commits, redactions);
+}
+
+fn finish_pairs(pairs: &mut [(Commit, Commit)], filter: &CommitFilter, opts: &CompareOptions) {
+    for (commit1, commit2) in pairs {
+        finish(
+            std::slice::from_mut(commit1),
+            &filter.redactions,
+            &filter.pseudonyms,
+            opts,
+        );
+        finish(
+            std::slice::from_mut(commit2),
+            &filter.redactions,
+            &filter.pseudonyms,
+            opts,
+        );
+    }
+}
+
+/// Keeps one commit per summary key, the one with the latest date. Commits sharing the latest
+/// date keep the one git listed first, which is the newest.
+fn dedupe(commits: Vec<Commit>, opts: &CompareOptions) -> Vec<Commit> {
+    let mut latest: HashMap<String, usize> = HashMap::new();
+    for (i, commit) in commits.iter().enumerate() {
+        latest
+            .entry(summary_key(&commit.summary, opts))
+            .and_modify(|j| {
+                if commit.author_date > commits[*j].author_date {
+                    *j = i;
+                }
+            })
+            .or_insert(i);
+    }
+
+    let kept: HashSet<usize> = latest.into_values().collect();
+
+    commits
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| kept.contains(i))
+        .map(|(_, commit)| commit)
+        .collect()
+}
+
+/// Drops the `Revert "X"` commits along with the older `X` commit they revert, for reverts
+/// nested up to `max_depth` times, so `0` drops nothing.
+fn collapse_reverts(commits: Vec<Commit>, max_depth: usize) -> Vec<Commit> {
+    if max_depth == 0 {
+        return commits;
+    }
+
+    let mut dropped = vec![false; commits.len()];
+
+    // Git lists the newest commits first, so the reverted commit comes after its revert. Going
+    // from the newest revert, a revert of a revert is paired before the revert it undoes.
+    for i in 0..commits.len() {
+        if dropped[i] || revert_depth(&commits[i].summary) > max_depth {
+            continue;
+        }
+
+        let Some(target) = revert_target(&commits[i].summary) else {
+            continue;
+        };
+
+        if let Some(j) =
+            (i + 1..commits.len()).find(|&j| !dropped[j] && commits[j].summary == target)
+        {
+            dropped[i] = true;
+            dropped[j] = true;
+        }
+    }
+
+    commits
+        .into_iter()
+        .zip(dropped)
+        .filter(|(_, dropped)| !dropped)
+        .map(|(commit, _)| commit)
+        .collect()
+}
+
+fn revert_target(summary: &str) -> Option<&str> {
+    summary.strip_prefix("Revert \"")?.strip_suffix('"')
+}
+
+fn revert_depth(mut summary: &str) -> usize {
+    let mut depth = 0;
+    while let Some(target) = revert_target(summary) {
+        depth += 1;
+        summary = target;
+    }
+
+    depth
+}
+
+fn include_types(mut commits: Vec<Commit>, types: &[String]) -> Vec<Commit> {
+    if types.is_empty() {
+        return commits;
+    }
+
+    commits.retain(|commit| has_type(commit, types));
+
+    commits
+}
+
+fn body_contains(mut commits: Vec<Commit>, text: Option<&str>) -> Vec<Commit> {
+    if let Some(text) = text {
+        commits.retain(|commit| has_in_body(commit, text));
+    }
+
+    commits
+}
+
+fn has_in_body(commit: &Commit, text: &str) -> bool {
+    commit
+        .body
+        .as_deref()
+        .is_some_and(|body| body.contains(text))
+}
+
+/// Maps the lines of the bodies of `commits` to the hash of their commit. Squash merges on GitHub
+/// and GitLab list the summaries of the squashed commits in the body, as `* summary`.
+fn squashed_summaries(commits: &[Commit], opts: &CompareOptions) -> HashMap<String, String> {
+    let mut squashed = HashMap::new();
+
+    for commit in commits {
+        let Some(body) = &commit.body else {
+            continue;
+        };
+
+        for line in body.lines() {
+            let line = line.trim();
+            let line = line
+                .strip_prefix("* ")
+                .or_else(|| line.strip_prefix("- "))
+                .unwrap_or(line);
+
+            if !line.is_empty() {
+                squashed
+                    .entry(summary_key(line, opts))
+                    .or_insert_with(|| commit.hash.clone());
+            }
+        }
+    }
+
+    squashed
+}
+
+fn mark_squashed(
+    commits: &mut [Commit],
+    squashed: &HashMap<String, String>,
+    opts: &CompareOptions,
+) {
+    for commit in commits {
+        commit.squashed_into = squashed.get(&summary_key(&commit.summary, opts)).cloned();
+    }
+}
+
+fn has_type(commit: &Commit, types: &[String]) -> bool {
+    commit.conventional().is_some_and(|info| {
+        types
+            .iter()
+            .any(|commit_type| commit_type.eq_ignore_ascii_case(&info.commit_type))
+    })
+}
+
+fn sort(mut commits: Vec<Commit>, opts: &CompareOptions) -> Vec<Commit> {
+    if let Some(sort_order) = opts.sort_order {
+        commits.sort_by(|a, b| order_by_date(a, b, sort_order, opts));
+    }
+
+    commits
+}
+
+fn order_by_date(a: &Commit, b: &Commit, sort_order: SortOrder, opts: &CompareOptions) -> Ordering {
+    let by_date = match sort_order {
+        SortOrder::Ascending => a.author_date.cmp(&b.author_date),
+        SortOrder::Descending => b.author_date.cmp(&a.author_date),
+    };
+
+    if opts.break_ties_by_hash {
+        by_date.then_with(|| a.hash.cmp(&b.hash))
+    } else {
+        by_date
+    }
+}
+
+/// Returns the summary used to match commits, which is left untouched on the `Commit` itself.
+fn summary_key(summary: &str, opts: &CompareOptions) -> String {
+    let summary = match parse_conventional(summary) {
+        Some(info) if opts.ignore_conventional_prefix => info.description,
+        _ => summary.to_string(),
+    };
+
+    let summary = if opts.normalize_whitespace {
+        summary.split_whitespace().collect::<Vec<&str>>().join(" ")
+    } else {
+        summary
+    };
+
+    match &opts.key_prefix {
+        Some(KeyPrefix::Length(length)) => summary.chars().take(*length).collect(),
+        Some(KeyPrefix::Delimiter(delimiter)) => match summary.split_once(delimiter.as_str()) {
+            Some((prefix, _)) => prefix.to_string(),
+            None => summary,
+        },
+        None => summary,
+    }
+}
+
+fn subtract<F>(commits1: Vec<Commit>, commits2: &[Commit], key_fn: F) -> Vec<Commit>
+where
+    F: Fn(&Commit) -> String,
+{
+    partition_matched(commits1, commits2, key_fn).0
+}
+
+/// Splits `commits1` into the commits without a match in `commits2` and the ones with one,
+/// paired with their match.
+fn partition_matched<F>(
+    commits1: Vec<Commit>,
+    commits2: &[Commit],
+    key_fn: F,
+) -> (Vec<Commit>, Vec<(Commit, Commit)>)
+where
+    F: Fn(&Commit) -> String,
+{
+    // Git lists the newest commits first, so a repeated key keeps its newest commit.
+    let mut commits2_by_key: HashMap<String, &Commit> = HashMap::new();
+    for commit in commits2 {
+        commits2_by_key.entry(key_fn(commit)).or_insert(commit);
+    }
+
+    let mut unmatched = Vec::new();
+    let mut matched = Vec::new();
+
+    for commit in commits1 {
+        match commits2_by_key.get(&key_fn(&commit)) {
+            Some(commit2) => matched.push((commit, (*commit2).clone())),
+            None => unmatched.push(commit),
+        }
+    }
+
+    (unmatched, matched)
+}
+
+fn subtract_both<F>(
+    commits1: Vec<Commit>,
+    commits2: Vec<Commit>,
+    key_fn: F,
+) -> (Vec<Commit>, Vec<Commit>)
+where
+    F: Fn(&Commit) -> String,
+{
+    let only_in_commits1 = subtract(commits1.clone(), &commits2, &key_fn);
+    let only_in_commits2 = subtract(commits2, &commits1, &key_fn);
+
+    (only_in_commits1, only_in_commits2)
+}
+
+fn subtract_fuzzy(
+    commits1: Vec<Commit>,
+    commits2: &[Commit],
+    threshold: f64,
+    opts: &CompareOptions,
+) -> Vec<Commit> {
+    let summaries2: Vec<Vec<char>> = commits2
+        .iter()
+        .map(|commit| summary_key(&commit.summary, opts).chars().collect())
+        .collect();
+
+    commits1
+        .into_iter()
+        .filter(|commit| {
+            let summary1: Vec<char> = summary_key(&commit.summary, opts).chars().collect();
+
+            !summaries2
+                .iter()
+                .any(|summary2| similarity(&summary1, summary2) >= threshold)
+        })
+        .collect()
+}
+
+fn pair_fuzzy(
+    commits1: Vec<Commit>,
+    commits2: &[Commit],
+    threshold: f64,
+    opts: &CompareOptions,
+) -> Vec<(Commit, Commit)> {
+    let summaries2: Vec<Vec<char>> = commits2
+        .iter()
+        .map(|commit| summary_key(&commit.summary, opts).chars().collect())
+        .collect();
+
+    commits1
+        .into_iter()
+        .filter_map(|commit| {
+            let summary1: Vec<char> = summary_key(&commit.summary, opts).chars().collect();
+
+            summaries2
+                .iter()
+                .position(|summary2| similarity(&summary1, summary2) >= threshold)
+                .map(|i| (commit, commits2[i].clone()))
+        })
+        .collect()
+}
+
+/// Returns `1.0` for equal strings, down to `0.0` for strings that share nothing.
+fn similarity(a: &[char], b: &[char]) -> f64 {
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+
+    1.0 - levenshtein(a, b) as f64 / max_len as f64
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, char_a) in a.iter().enumerate() {
+        current[0] = i + 1;
+
+        for (j, char_b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(char_a != char_b);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+// `commit.hash` is usually abbreviated while `git patch-id` reports full hashes, so the full hash
+// is looked up as the first key that starts with the abbreviated one.
+fn patch_id_key(
+    patch_ids: &BTreeMap<String, String>,
+    commit: &Commit,
+    opts: &CompareOptions,
+) -> String {
+    match patch_ids.range(commit.hash.clone()..).next() {
+        Some((full_hash, patch_id)) if full_hash.starts_with(&commit.hash) => {
+            format!("patch:{patch_id}")
+        }
+        _ => format!("summary:{}", summary_key(&commit.summary, opts)),
+    }
+}
+
+fn tree_key(commit: &Commit, opts: &CompareOptions) -> String {
+    match &commit.tree {
+        Some(tree) => format!("tree:{tree}"),
+        None => format!("summary:{}", summary_key(&commit.summary, opts)),
+    }
+}
+
+fn change_id_key(
+    change_ids: &HashMap<String, String>,
+    commit: &Commit,
+    opts: &CompareOptions,
+) -> String {
+    match change_ids.get(&commit.hash) {
+        Some(change_id) => format!("change:{change_id}"),
+        None => format!("summary:{}", summary_key(&commit.summary, opts)),
+    }
+}
+
+/// Returns a map from commit hash, abbreviated unless the full hashes are read, to the value of
+/// its `Change-Id:` trailer for every commit in `revision` that has one.
+fn get_change_ids(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+) -> Result<HashMap<String, String>, DiffError> {
+    let output = run(
+        git_command(opts).current_dir(repo_path).args([
+            "log",
+            revision,
+            &format!(
+                "--format={}%x1f%(trailers:key=Change-Id,valueonly,separator=%x1f)",
+                hash_placeholder(opts)
+            ),
+        ]),
+        opts,
+    )?;
+
+    if !output.status.success() {
+        return Err(git_error(&output));
+    }
+
+    let change_ids = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\x1f');
+            let commit_hash = fields.next()?;
+            let change_id = fields.next().filter(|change_id| !change_id.is_empty())?;
+            Some((commit_hash.to_string(), change_id.trim().to_string()))
+        })
+        .collect();
+
+    Ok(change_ids)
+}
+
+/// Returns a map from full commit hash to its stable patch id for every commit in `revision`.
+fn get_patch_ids(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+) -> Result<BTreeMap<String, String>, DiffError> {
+    let mut git_log_cmd = git_command(opts);
+    git_log_cmd
+        .current_dir(repo_path)
+        .args(["log", "-p", "--no-color", "--no-ext-diff", revision]);
+    print_command(&git_log_cmd, opts);
+
+    let mut git_log = git_log_cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+
+    // Drained on its own, like in `run`, so a chatty `git log` doesn't block on a full pipe.
+    let git_log_stderr = read_pipe(git_log.stderr.take());
+    let git_log_stdout = git_log
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::other("Failed to read the git log output"))?;
+
+    let patch_id_output = run(
+        git_command(opts)
+            .current_dir(repo_path)
+            .args(["patch-id", "--stable"])
+            .stdin(git_log_stdout),
+        opts,
+    );
+
+    // `git log` would be left stalled on a full pipe otherwise.
+    let patch_id_output = match patch_id_output {
+        Ok(output) => output,
+        Err(err) => {
+            let _ = git_log.kill();
+            let _ = git_log.wait();
+            return Err(err);
+        }
+    };
+
+    let git_log_status = git_log.wait()?;
+    let git_log_stderr = join_pipe(git_log_stderr)?;
+
+    if !git_log_status.success() {
+        return Err(DiffError::GitFailed {
+            code: git_log_status.code(),
+            stderr: String::from_utf8_lossy(&git_log_stderr).trim().to_string(),
+        });
+    }
+
+    if !patch_id_output.status.success() {
+        return Err(git_error(&patch_id_output));
+    }
+
+    let patch_ids = String::from_utf8_lossy(&patch_id_output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (patch_id, commit_hash) = line.split_once(' ')?;
+            Some((commit_hash.to_string(), patch_id.to_string()))
+        })
+        .collect();
+
+    Ok(patch_ids)
+}
+
+/// Reads the commits of `ref_` in `repo_path`, newest first, with the log options of `opts`, to
+/// build other comparisons on. The repository path of `opts` is ignored, and nothing is
+/// excluded or matched.
+pub fn branch_commits(
+    repo_path: &str,
+    ref_: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let repo_path = prepare(Some(Path::new(repo_path)), &[ref_], RefCheck::Commits, opts)?;
+
+    let mut commits = read_commits(&repo_path, ref_, opts)?;
+    finish(
+        &mut commits,
+        &redactions(opts)?,
+        &Pseudonyms::default(),
+        opts,
+    );
+
+    Ok(commits)
+}
+
+/// Returns the patch of each commit in `repo_path` as `git format-patch --stdout` writes it, in
+/// the order given, such as the unique commits of a comparison to replay elsewhere with
+/// `git am`, which expects them oldest first. Merges have no patch and give an empty string.
+/// The repository path of `opts` is ignored.
+pub fn format_patches(
+    commits: &[Commit],
+    repo_path: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<String>, DiffError> {
+    let repo_path = prepare(Some(Path::new(repo_path)), &[], RefCheck::Commits, opts)?;
+
+    commits
+        .iter()
+        .map(|commit| {
+            let output = run(
+                git_command(opts).current_dir(&repo_path).args([
+                    "format-patch",
+                    "--stdout",
+                    "-1",
+                    &commit.hash,
+                    "--",
+                ]),
+                opts,
+            )?;
+            if !output.status.success() {
+                return Err(git_error(&output));
+            }
+
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        })
+        .collect()
+}
+
+/// Runs `git log` on `revision` in `repo_path`, a directory of a repository, with the filters
+/// of `opts`. The output is meant for `parse_git_output`.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(opts), err))]
+pub fn get_branch_commits(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+) -> Result<Output, DiffError> {
+    read_log(repo_path, revision, opts, &[])
+}
+
+/// Runs `git log` like `get_branch_commits`, with `extra_args` added to the options.
+fn read_log(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+    extra_args: &[&str],
+) -> Result<Output, DiffError> {
+    ensure_followable(opts)?;
+    ensure_separators(opts)?;
+
+    let mut retries = 0;
+
+    loop {
+        let output = run(
+            &mut log_command_with(repo_path, revision, opts, extra_args),
+            opts,
+        )?;
+
+        if output.status.success() {
+            print_git_warnings(&output, opts);
+            return Ok(output);
+        }
+
+        if retries == opts.retries || !is_transient(&output) {
+            return Err(git_error(&output));
+        }
+
+        // Waits 100ms, then 200ms, 400ms, and so on, up to 6.4s.
+        thread::sleep(Duration::from_millis(100 << retries.min(6)));
+        retries += 1;
+    }
+}
+
+// Git would otherwise fail with "--follow requires exactly one pathspec", or follow nothing
+// without any.
+fn ensure_followable(opts: &CompareOptions) -> Result<(), DiffError> {
+    if opts.follow && opts.paths.len() != 1 {
+        return Err(DiffError::FollowNeedsOnePath(opts.paths.len()));
+    }
+
+    Ok(())
+}
+
+// A threshold above `1.0` would match nothing, and one below `0.0` everything.
+fn ensure_threshold(opts: &CompareOptions) -> Result<(), DiffError> {
+    match opts.match_strategy {
+        MatchStrategy::Fuzzy { threshold } if !(0.0..=1.0).contains(&threshold) => {
+            Err(DiffError::InvalidThreshold(threshold))
+        }
+        _ => Ok(()),
+    }
+}
+
+// `%x` writes a single byte, and the separators can't be a byte the output already has: the
+// newlines and tabs of the numstat lines, or the separators of the fields after the summary.
+fn ensure_separators(opts: &CompareOptions) -> Result<(), DiffError> {
+    let layout = Layout::new(opts);
+    let taken = [
+        '\n',
+        '\r',
+        '\t',
+        EMAIL_SEPARATOR,
+        RELATIVE_DATE_SEPARATOR,
+        COMMIT_DATE_SEPARATOR,
+        REFS_SEPARATOR,
+        TREE_SEPARATOR,
+        SIGNATURE_SEPARATOR,
+        EXTRA_SEPARATOR,
+    ];
+
+    for separator in [layout.record_separator, layout.field_separator] {
+        if !separator.is_ascii_control() || taken.contains(&separator) {
+            return Err(DiffError::InvalidSeparator(separator));
+        }
+    }
+    if layout.record_separator == layout.field_separator {
+        return Err(DiffError::InvalidSeparator(layout.field_separator));
+    }
+
+    Ok(())
+}
+
+/// Prints what git wrote to stderr although it succeeded, like a warning about an ambiguous ref,
+/// which would otherwise go unnoticed.
+fn print_git_warnings(output: &Output, opts: &CompareOptions) {
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let line = line.strip_prefix("warning: ").unwrap_or(line);
+        warn(opts, format_args!("git: {line}"));
+    }
+}
+
+/// The messages of the failures that can go away on their own, like a lock held by a
+/// concurrent git process or a network filesystem hiccup.
+const TRANSIENT_ERRORS: &[&str] = &[
+    ".lock': File exists",
+    "cannot lock ref",
+    "Resource temporarily unavailable",
+    "Stale file handle",
+];
+
+fn is_transient(output: &Output) -> bool {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    TRANSIENT_ERRORS
+        .iter()
+        .any(|message| stderr.contains(message))
+}
+
+fn hash_placeholder(opts: &CompareOptions) -> &'static str {
+    if opts.full_hash {
+        "%H"
+    } else {
+        "%h"
+    }
+}
+
+/// Builds the `git log` command `get_branch_commits` runs, so its arguments can be inspected.
+pub fn log_command(repo_path: &str, revision: &str, opts: &CompareOptions) -> Command {
+    log_command_with(repo_path, revision, opts, &[])
+}
+
+fn log_command_with(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+    extra_args: &[&str],
+) -> Command {
+    let mut git_log_cmd = git_command(opts);
+    git_log_cmd.current_dir(repo_path).args([
+        "log",
+        revision,
+        "--date=format:%Y-%m-%d",
+        // Messages are re-encoded to UTF-8 from the encoding they were committed with, so they
+        // survive `from_utf8_lossy`.
+        "--encoding=UTF-8",
+    ]);
+    git_log_cmd.args(extra_args);
+
+    // In the order the parsers split them off, last first.
+    let layout = Layout::new(opts);
+    let mut extra = String::new();
+    if layout.email {
+        extra.push_str("%x1b%ae");
+    }
+    if layout.relative_date {
+        extra.push_str("%x1c%ar");
+    }
+    if layout.commit_date {
+        extra.push_str("%x18%cd");
+    }
+    if layout.refs {
+        extra.push_str("%x1a%D");
+    }
+    if layout.tree {
+        extra.push_str("%x19%T");
+    }
+    if layout.signature {
+        extra.push_str("%x17%G?");
+    }
+    let rs = format!("%x{:02x}", u32::from(layout.record_separator));
+    let fs = format!("%x{:02x}", u32::from(layout.field_separator));
+    if layout.extra {
+        extra.push_str("%x1d");
+        extra.push_str(&opts.extra_placeholders.join(&fs));
+    }
+
+    let hash = hash_placeholder(opts);
+    let body = if layout.body {
+        format!("{fs}%b")
+    } else {
+        String::new()
+    };
+    git_log_cmd.arg(format!(
+        "--pretty=format:{rs}{hash}{fs}%ad{fs}%an{fs}%s{body}{extra}{rs}"
+    ));
+
+    if opts.reads_numstat() {
+        git_log_cmd.arg("--numstat");
+    }
+
+    if opts.follow {
+        git_log_cmd.arg("--follow");
+    }
+
+    add_log_filters(&mut git_log_cmd, opts);
+
+    git_log_cmd
+}
+
+/// Adds the arguments `git log` and `git rev-list` share to only list the commits `opts` reads.
+fn add_log_filters(cmd: &mut Command, opts: &CompareOptions) {
+    // `git log` only filters by commit date, so `read_commits` and `cherry_marked_commits`
+    // filter by author date themselves.
+    if opts.date_kind == DateKind::Commit {
+        if let Some(since) = &opts.since {
+            cmd.arg(format!("--since={since}"));
+        }
+
+        if let Some(until) = &opts.until {
+            cmd.arg(format!("--until={until}"));
+        }
+    }
+
+    // Git matches the pattern against both the author name and email.
+    if let Some(author) = &opts.author {
+        cmd.arg(format!("--author={author}"));
+    }
+
+    if let Some(max_count) = opts.max_count {
+        cmd.args(["-n", &max_count.to_string()]);
+    }
+
+    if let Some(skip) = opts.skip {
+        cmd.arg(format!("--skip={skip}"));
+    }
+
+    match opts.merge_filter {
+        MergeFilter::All => {}
+        MergeFilter::NoMerges => {
+            cmd.arg("--no-merges");
+        }
+        MergeFilter::OnlyMerges => {
+            cmd.arg("--merges");
+        }
+    }
+
+    if opts.first_parent {
+        cmd.arg("--first-parent");
+    }
+
+    if !opts.paths.is_empty() {
+        cmd.arg("--").args(&opts.paths);
+    }
+}
+
+// `Command` is debug-formatted like `cd "repo" && "git" "log" ...`, ready to be pasted.
+fn print_command(cmd: &Command, opts: &CompareOptions) {
+    if opts.verbosity >= Verbosity::Verbose {
+        eprintln!("+ {cmd:?}");
+    }
+}
+
+fn warn(opts: &CompareOptions, message: fmt::Arguments) {
+    if opts.verbosity >= Verbosity::Normal {
+        eprintln!("Warning: {message}");
+    }
+}
+
+// Marked like a shell comment, so it reads along the commands printed before it.
+fn note(opts: &CompareOptions, message: fmt::Arguments) {
+    if opts.verbosity >= Verbosity::Verbose {
+        eprintln!("# {message}");
+    }
+}
+
+/// Runs `cmd` to completion, killing it when it runs longer than the timeout of `opts`.
+fn run(cmd: &mut Command, opts: &CompareOptions) -> Result<Output, DiffError> {
+    print_command(cmd, opts);
+
+    let Some(timeout) = opts.timeout else {
+        return cmd.output().map_err(spawn_error);
+    };
+
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+
+    // The pipes are drained while waiting so a chatty command doesn't block on a full pipe.
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(DiffError::Timeout(timeout));
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    Ok(Output {
+        status,
+        stdout: join_pipe(stdout)?,
+        stderr: join_pipe(stderr)?,
+    })
+}
+
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buf)?;
+        }
+        Ok(buf)
+    })
+}
+
+fn join_pipe(reader: JoinHandle<io::Result<Vec<u8>>>) -> io::Result<Vec<u8>> {
+    reader
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn git_command(opts: &CompareOptions) -> Command {
+    match &opts.git_binary {
+        Some(git_binary) => Command::new(git_binary),
+        None => Command::new("git"),
+    }
+}
+
+fn spawn_error(err: io::Error) -> DiffError {
+    if err.kind() == io::ErrorKind::NotFound {
+        DiffError::GitNotInstalled
+    } else {
+        DiffError::Io(err)
+    }
+}
+
+fn git_error(output: &Output) -> DiffError {
+    DiffError::GitFailed {
+        code: output.status.code(),
+        stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    }
+}
+
+/// With the `fetch_first` option, runs `git fetch` for every remote a ref is under, such as
+/// `origin` for `origin/main`. Refs that aren't remote-tracking ones fetch nothing.
+fn fetch_remotes(repo_path: &str, refs: &[&str], opts: &CompareOptions) -> Result<(), DiffError> {
+    if !opts.fetch_first {
+        return Ok(());
+    }
+
+    let output = run(git_command(opts).current_dir(repo_path).arg("remote"), opts)?;
+    if !output.status.success() {
+        return Err(git_error(&output));
+    }
+
+    let remotes = String::from_utf8_lossy(&output.stdout);
+    let mut fetched = HashSet::new();
+
+    for ref_ in refs {
+        let ref_ = ref_.strip_prefix("refs/remotes/").unwrap_or(ref_);
+        let Some(remote) = remotes
+            .lines()
+            .find(|remote| ref_.starts_with(&format!("{remote}/")))
+        else {
+            continue;
+        };
+
+        if !fetched.insert(remote) {
+            continue;
+        }
+
+        let output = run(
+            git_command(opts)
+                .current_dir(repo_path)
+                .args(["fetch", "--quiet", remote]),
+            opts,
+        )?;
+        if !output.status.success() {
+            return Err(git_error(&output));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "git2")]
+fn verify_ref(repo_path: &str, ref_: &str, _opts: &CompareOptions) -> Result<(), DiffError> {
+    revwalk::verify_ref(repo_path, ref_)
+}
+
+/// Checks that `ref_` names a commit, so branches, tags, and expressions like `HEAD~5` are all
+/// accepted.
+#[cfg(not(feature = "git2"))]
+fn verify_ref(repo_path: &str, ref_: &str, opts: &CompareOptions) -> Result<(), DiffError> {
+    let output = run(
+        git_command(opts)
+            .current_dir(repo_path)
+            .args(["rev-parse", "--verify", "--quiet"])
+            .arg(format!("{ref_}^{{commit}}")),
+        opts,
+    )?;
+
+    if !output.status.success() {
+        return Err(DiffError::RefNotFound(ref_.to_string()));
+    }
+
+    Ok(())
+}
+
+// Unlike `--verify`, a plain `rev-parse` takes ranges, and the `--` makes it fail on anything
+// that isn't a revision rather than read it as a path.
+fn verify_range(repo_path: &str, range: &str, opts: &CompareOptions) -> Result<(), DiffError> {
+    let output = run(
+        git_command(opts)
+            .current_dir(repo_path)
+            .args(["rev-parse", "--quiet", range, "--"]),
+        opts,
+    )?;
+
+    if !output.status.success() {
+        return Err(DiffError::InvalidRange(range.to_string()));
+    }
+
+    Ok(())
+}
+
+fn resolve(repo_path: &str, ref_: &str, opts: &CompareOptions) -> Result<ResolvedRef, DiffError> {
+    let output = run(
+        git_command(opts)
+            .current_dir(repo_path)
+            .args(["rev-parse", "--verify", "--quiet"])
+            .arg(format!("{ref_}^{{commit}}")),
+        opts,
+    )?;
+    if !output.status.success() {
+        return Err(DiffError::RefNotFound(ref_.to_string()));
+    }
+    let hash = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if !is_head(ref_) {
+        return Ok(ResolvedRef {
+            name: ref_.to_string(),
+            hash,
+            branch: None,
+            detached: false,
+        });
+    }
+
+    // `symbolic-ref` fails quietly when `HEAD` is detached.
+    let output = run(
+        git_command(opts).current_dir(repo_path).args([
+            "symbolic-ref",
+            "--quiet",
+            "--short",
+            "HEAD",
+        ]),
+        opts,
+    )?;
+    let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let branch = (output.status.success() && !branch.is_empty()).then_some(branch);
+
+    Ok(ResolvedRef {
+        name: ref_.to_string(),
+        hash,
+        detached: branch.is_none(),
+        branch,
+    })
+}
+
+// `@` is git's shorthand for `HEAD`.
+fn is_head(ref_: &str) -> bool {
+    ref_ == "HEAD" || ref_ == "@"
+}
+
+/// Notes which commit a detached `HEAD` is at, or fails if `reject_detached_head` is set, when
+/// `revision` is `HEAD` or a range that ends at it.
+fn check_head(repo_path: &str, revision: &str, opts: &CompareOptions) -> Result<(), DiffError> {
+    for ref_ in range_ends(revision) {
+        if !is_head(ref_) {
+            continue;
+        }
+
+        let resolved = resolve(repo_path, ref_, opts)?;
+        if resolved.detached {
+            if opts.reject_detached_head {
+                return Err(DiffError::DetachedHead(resolved.hash));
+            }
+            note(opts, format_args!("comparing {resolved}"));
+        }
+    }
+
+    Ok(())
+}
+
+// Like git, an end left out of `A..B` or `A...B` is `HEAD`.
+fn range_ends(revision: &str) -> Vec<&str> {
+    let ends = revision
+        .split_once("...")
+        .or_else(|| revision.split_once(".."));
+
+    match ends {
+        Some((from, to)) => [from, to]
+            .into_iter()
+            .map(|end| if end.is_empty() { "HEAD" } else { end })
+            .collect(),
+        None => vec![revision],
+    }
+}
+
+/// Fails with `DiffError::NoCommonAncestor` when the refs have unrelated histories, since every
+/// commit would then look unique, unless the `allow_unrelated` option is set.
+fn ensure_related(
+    repo_path: &str,
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<(), DiffError> {
+    if opts.allow_unrelated {
+        return Ok(());
+    }
+
+    match merge_base(repo_path, ref1, ref2, opts)? {
+        Some(_) => Ok(()),
+        None => Err(DiffError::NoCommonAncestor(
+            ref1.to_string(),
+            ref2.to_string(),
+        )),
+    }
+}
+
+/// Returns the hash of the best common ancestor of the refs, or `None` if they have none.
+fn merge_base(
+    repo_path: &str,
+    ref1: &str,
+    ref2: &str,
+    opts: &CompareOptions,
+) -> Result<Option<String>, DiffError> {
+    let output = run(
+        git_command(opts)
+            .current_dir(repo_path)
+            .args(["merge-base", ref1, ref2]),
+        opts,
+    )?;
+
+    // `git merge-base` exits with 1 when there's no common ancestor.
+    match output.status.code() {
+        Some(0) => Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        )),
+        Some(1) => Ok(None),
+        _ => Err(git_error(&output)),
+    }
+}
+
+/// Fetches the commits of both revisions at the same time, each in its own git process.
+fn fetch_both_commits(
+    repo_path1: &str,
+    revision1: &str,
+    repo_path2: &str,
+    revision2: &str,
+    opts: &CompareOptions,
+    cache: Option<&CommitCache>,
+) -> Result<(Vec<Commit>, Vec<Commit>), DiffError> {
+    thread::scope(|scope| {
+        // Spans don't follow into other threads on their own.
+        #[cfg(feature = "tracing")]
+        let span = tracing::Span::current();
+
+        let commits1 = scope.spawn(move || {
+            #[cfg(feature = "tracing")]
+            let _entered = span.enter();
+
+            fetch_commits(repo_path1, revision1, opts, cache)
+        });
+        let commits2 = fetch_commits(repo_path2, revision2, opts, cache);
+        let commits1 = commits1
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+        Ok((commits1?, commits2?))
+    })
+}
+
+fn fetch_commits(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+    cache: Option<&CommitCache>,
+) -> Result<Vec<Commit>, DiffError> {
+    let cached = cache.and_then(|cache| cache.get(repo_path, revision, opts));
+    let commits = match cached {
+        Some(commits) => commits,
+        None => {
+            let commits = read_commits(repo_path, revision, opts)?;
+            if let Some(cache) = cache {
+                cache.insert(repo_path, revision, opts, commits.clone());
+            }
+            commits
+        }
+    };
+
+    opts.report(ProgressEvent::Fetched {
+        revision: revision.to_string(),
+        commits: commits.len(),
+    });
+
+    note(
+        opts,
+        format_args!("read {} commits of '{revision}'", commits.len()),
+    );
+
+    if opts.max_count == Some(commits.len()) {
+        match opts.skip {
+            Some(skip) => warn(
+                opts,
+                format_args!(
+                    "only {} commits of '{revision}', after skipping {skip}, were compared",
+                    commits.len()
+                ),
+            ),
+            None => warn(
+                opts,
+                format_args!(
+                    "only the newest {} commits of '{revision}' were compared",
+                    commits.len()
+                ),
+            ),
+        }
+    }
+
+    Ok(commits)
+}
+
+fn read_commits(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    if reads_author_bounds(opts) {
+        return read_authored_between(repo_path, revision, opts);
+    }
+
+    // libgit2 can't walk symmetric differences like `A...B`.
+    #[cfg(feature = "git2")]
+    let commits = if revwalk::supports(opts) && !revision.contains("...") {
+        revwalk::branch_commits(repo_path, revision, opts)?
+    } else {
+        parse_git_output(get_branch_commits(repo_path, revision, opts)?, opts)
+    };
+
+    #[cfg(not(feature = "git2"))]
+    let commits = parse_git_output(get_branch_commits(repo_path, revision, opts)?, opts);
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!(revision, commits = commits.len(), "fetched the commits");
+
+    Ok(commits)
+}
+
+/// Reads the commits of `revision` authored between the `since` and `until` dates.
+fn read_authored_between(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let (since, until) = date_bounds(repo_path, opts)?;
+
+    // The author timestamp is read as the first extra placeholder, then taken out of them.
+    let mut extra_placeholders = vec!["%at".to_string()];
+    extra_placeholders.extend(opts.extra_placeholders.iter().cloned());
+    let dated_opts = CompareOptions {
+        since: None,
+        until: None,
+        extra_placeholders,
+        ..opts.clone()
+    };
+
+    let mut commits = read_commits(repo_path, revision, &dated_opts)?;
+    commits.retain_mut(|commit| {
+        let timestamp = if commit.extra.is_empty() {
+            None
+        } else {
+            Some(commit.extra.remove(0))
+        };
+        authored_between(timestamp.as_deref(), (since, until))
+    });
+
+    Ok(commits)
+}
+
+/// Whether the `since` and `until` options bound the author dates, which `git log` can't.
+fn reads_author_bounds(opts: &CompareOptions) -> bool {
+    opts.date_kind == DateKind::Author && (opts.since.is_some() || opts.until.is_some())
+}
+
+/// Whether the author `timestamp` read with `%at` falls within the `date_bounds`.
+fn authored_between(timestamp: Option<&str>, (since, until): (Option<i64>, Option<i64>)) -> bool {
+    timestamp
+        .and_then(|timestamp| timestamp.parse::<i64>().ok())
+        .is_some_and(|timestamp| {
+            since.is_none_or(|since| timestamp >= since)
+                && until.is_none_or(|until| timestamp <= until)
+        })
+}
+
+/// Resolves the `since` and `until` dates to Unix timestamps, which `git rev-parse` prints as
+/// `--max-age` and `--min-age`.
+fn date_bounds(
+    repo_path: &str,
+    opts: &CompareOptions,
+) -> Result<(Option<i64>, Option<i64>), DiffError> {
+    let mut cmd = git_command(opts);
+    cmd.current_dir(repo_path).arg("rev-parse");
+    if let Some(since) = &opts.since {
+        cmd.arg(format!("--since={since}"));
+    }
+    if let Some(until) = &opts.until {
+        cmd.arg(format!("--until={until}"));
+    }
+
+    let output = run(&mut cmd, opts)?;
+    if !output.status.success() {
+        return Err(git_error(&output));
+    }
+
+    let mut bounds = (None, None);
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(since) = line.strip_prefix("--max-age=") {
+            bounds.0 = since.parse().ok();
+        } else if let Some(until) = line.strip_prefix("--min-age=") {
+            bounds.1 = until.parse().ok();
+        }
+    }
+
+    Ok(bounds)
+}
+
+/// The commits read from the logs of the refs, owned by the caller and passed to
+/// `compare_branches_both_cached` to reuse them across comparisons. The commits are kept until
+/// they are invalidated, so a ref that moves in between is compared as it was.
+#[derive(Debug, Default)]
+pub struct CommitCache {
+    entries: Mutex<HashMap<CacheKey, Vec<Commit>>>,
+}
+
+// The arguments of the `git log` command cover every option the log depends on, except `since`
+// and `until` with `DateKind::Author`, which `read_authored_between` applies afterwards.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    repo_path: String,
+    revision: String,
+    args: Vec<String>,
+    date_kind: DateKind,
+    since: Option<String>,
+    until: Option<String>,
+}
+
+impl CommitCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the commits read for `ref_`, in every repository and with any options.
+    pub fn invalidate(&self, ref_: &str) {
+        self.lock().retain(|key, _| key.revision != ref_);
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn get(&self, repo_path: &str, revision: &str, opts: &CompareOptions) -> Option<Vec<Commit>> {
+        self.lock()
+            .get(&Self::key(repo_path, revision, opts))
+            .cloned()
+    }
+
+    fn insert(&self, repo_path: &str, revision: &str, opts: &CompareOptions, commits: Vec<Commit>) {
+        self.lock()
+            .insert(Self::key(repo_path, revision, opts), commits);
+    }
+
+    fn key(repo_path: &str, revision: &str, opts: &CompareOptions) -> CacheKey {
+        let command = log_command(repo_path, revision, opts);
+        let args = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+
+        CacheKey {
+            repo_path: repo_path.to_string(),
+            revision: revision.to_string(),
+            args,
+            date_kind: opts.date_kind,
+            since: opts.since.clone(),
+            until: opts.until.clone(),
+        }
+    }
+
+    // A panic while the lock is held can't leave the map half updated, so a poisoned lock is
+    // still used.
+    fn lock(&self) -> MutexGuard<'_, HashMap<CacheKey, Vec<Commit>>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Comparing against a ref without commits makes every commit look unique, which is more likely
+/// a wrong ref or filter than the intended result. With `paths`, though, a ref whose commits
+/// don't touch them is expected to list none, like with `git log`.
+fn ensure_not_empty(
+    ref_: &str,
+    commits: &[Commit],
+    opts: &CompareOptions,
+) -> Result<(), DiffError> {
+    if commits.is_empty() && opts.paths.is_empty() {
+        return Err(DiffError::EmptyRef(ref_.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Parses the output of `get_branch_commits` with `opts` into commits, newest first.
+// With `--numstat`, each commit line is followed by one `<added>\t<removed>\t<path>` line per
+// changed file, which are added up into the commit above them.
+pub fn parse_git_output(raw_commits: Output, opts: &CompareOptions) -> Vec<Commit> {
+    let layout = Layout::new(opts);
+    let git_log_output_str = String::from_utf8_lossy(&raw_commits.stdout);
+    let mut commits: Vec<Commit> = Vec::new();
+
+    // Every commit is surrounded by record separators, so the chunks between them alternate
+    // between a commit and what git writes after it, the numstat lines or a newline.
+    for (i, chunk) in git_log_output_str
+        .split(layout.record_separator)
+        .skip(1)
+        .enumerate()
+    {
+        if i % 2 == 1 {
+            chunk
+                .lines()
+                .for_each(|line| add_numstat(&mut commits, line));
+        } else if let Some(commit) = parse_record(chunk, &layout) {
+            commits.push(commit);
+        }
+    }
+
+    commits
+}
+
+fn parse_line(line: &str, layout: &Layout) -> Option<Commit> {
+    let (line, suffixes) = split_suffixes(line.trim_end_matches('\r'), layout);
+    let mut commit = Commit::from_fields(line, layout.field_separator)?;
+    suffixes.apply(&mut commit);
+
+    Some(commit)
+}
+
+/// Parses a commit of the `git log` output, with its body if `layout` has one.
+fn parse_record(record: &str, layout: &Layout) -> Option<Commit> {
+    if !layout.body {
+        return parse_line(record, layout);
+    }
+
+    let (record, suffixes) = split_suffixes(record, layout);
+    // The body follows the fourth separator, and can have more of them itself.
+    let (index, _) = record.match_indices(layout.field_separator).nth(3)?;
+    let (line, body) = (&record[..index], &record[index + 1..]);
+    let mut commit = Commit::from_fields(line, layout.field_separator)?;
+    suffixes.apply(&mut commit);
+
+    let body = body.trim();
+    if !body.is_empty() {
+        commit.body = Some(body.to_string());
+    }
+
+    Some(commit)
+}
+
+/// The fields that follow the summary, or the body, of a commit of the `git log` output.
+struct Suffixes {
+    email: Option<String>,
+    relative_date: Option<String>,
+    commit_date: Option<String>,
+    refs: Option<String>,
+    tree: Option<String>,
+    signature: Option<String>,
+    extra: Vec<String>,
+}
+
+impl Suffixes {
+    fn apply(self, commit: &mut Commit) {
+        commit.email = self.email;
+        commit.relative_date = self.relative_date;
+        commit.commit_date = self.commit_date;
+        commit.refs = parse_decorations(self.refs.as_deref());
+        commit.tree = self.tree;
+        commit.signature = self
+            .signature
+            .as_deref()
+            .map(SignatureStatus::from_placeholder);
+        commit.extra = self.extra;
+    }
+}
+
+/// Splits off the fields `layout` has, last first, since they were appended in order.
+fn split_suffixes<'a>(text: &'a str, layout: &Layout) -> (&'a str, Suffixes) {
+    let (text, extra) = split_extra(text, layout);
+    let (text, signature) = split_suffix(text, SIGNATURE_SEPARATOR, layout.signature);
+    let (text, tree) = split_suffix(text, TREE_SEPARATOR, layout.tree);
+    let (text, refs) = split_suffix(text, REFS_SEPARATOR, layout.refs);
+    let (text, commit_date) = split_suffix(text, COMMIT_DATE_SEPARATOR, layout.commit_date);
+    let (text, relative_date) = split_suffix(text, RELATIVE_DATE_SEPARATOR, layout.relative_date);
+    let (text, email) = split_suffix(text, EMAIL_SEPARATOR, layout.email);
+
+    (
+        text,
+        Suffixes {
+            email,
+            relative_date,
+            commit_date,
+            refs,
+            tree,
+            signature,
+            extra,
+        },
+    )
+}
+
+/// Turns the `%D` decorations, such as `HEAD -> main, tag: v1.2.0, origin/main`, into the
+/// names they list.
+fn parse_decorations(decorations: Option<&str>) -> Vec<String> {
+    let Some(decorations) = decorations else {
+        return Vec::new();
+    };
+
+    decorations
+        .split(", ")
+        .flat_map(|decoration| decoration.split(" -> "))
+        .map(|name| name.strip_prefix("tag: ").unwrap_or(name).trim())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn split_suffix(text: &str, separator: char, present: bool) -> (&str, Option<String>) {
+    if !present {
+        return (text, None);
+    }
+
+    match text.rsplit_once(separator) {
+        Some((text, suffix)) => (text, Some(suffix.to_string())),
+        None => (text, None),
+    }
+}
+
+fn split_extra<'a>(text: &'a str, layout: &Layout) -> (&'a str, Vec<String>) {
+    if !layout.extra {
+        return (text, Vec::new());
+    }
+
+    match text.rsplit_once(EXTRA_SEPARATOR) {
+        Some((text, extra)) => (
+            text,
+            extra
+                .split(layout.field_separator)
+                .map(str::to_string)
+                .collect(),
+        ),
+        None => (text, Vec::new()),
+    }
+}
+
+fn add_numstat(commits: &mut [Commit], line: &str) {
+    if let (Some(numstat), Some(commit)) = (parse_numstat_line(line), commits.last_mut()) {
+        add_file(commit, numstat);
+    }
+}
+
+fn add_file(commit: &mut Commit, (additions, deletions, path): (usize, usize, &str)) {
+    commit.additions += additions;
+    commit.deletions += deletions;
+    commit.files.push(renamed_path(path));
+}
+
+/// The path of a file after the commit, out of the `old => new` and `dir/{old => new}/file`
+/// forms `--numstat` gives renames.
+fn renamed_path(path: &str) -> String {
+    let Some((old, new)) = path.split_once(" => ") else {
+        return path.to_string();
+    };
+
+    match (old.split_once('{'), new.split_once('}')) {
+        // A directory added or taken out by the rename leaves an empty side, as in `{ => dir}`.
+        (Some((prefix, _)), Some((renamed, suffix))) => {
+            format!("{prefix}{renamed}{suffix}").replace("//", "/")
+        }
+        _ => new.to_string(),
+    }
+}
+
+// Binary files are reported with `-` instead of line counts, and are counted as zero lines.
+fn parse_numstat_line(line: &str) -> Option<(usize, usize, &str)> {
+    let mut fields = line.splitn(3, '\t');
+    let additions = parse_numstat_count(fields.next()?)?;
+    let deletions = parse_numstat_count(fields.next()?)?;
+    let path = fields.next()?;
+
+    Some((additions, deletions, path))
+}
+
+fn parse_numstat_count(count: &str) -> Option<usize> {
+    if count == "-" {
+        Some(0)
+    } else {
+        count.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::{self, ExitStatus};
+
+    use super::*;
+
+    /// A scratch repository with a `main` branch in the temporary directory, removed on drop.
+    struct Fixture {
+        root: PathBuf,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("fdiff-{name}-{}", process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("repo")).unwrap();
+
+            let fixture = Fixture { root };
+            fixture.git(&["init", "--quiet", "--initial-branch=main"]);
+            fixture.commit("README.md", "Add the readme");
+            fixture
+        }
+
+        fn repo(&self) -> PathBuf {
+            self.root.join("repo")
+        }
+
+        fn git(&self, args: &[&str]) -> String {
+            self.git_at(None, args)
+        }
+
+        /// Runs git with both the author and the commit dates set to `date`, when there is one.
+        fn git_at(&self, date: Option<&str>, args: &[&str]) -> String {
+            let mut git = Command::new("git");
+            if let Some(date) = date {
+                git.env("GIT_AUTHOR_DATE", date)
+                    .env("GIT_COMMITTER_DATE", date);
+            }
+
+            let output = git
+                .current_dir(self.repo())
+                .args(["-c", "user.name=Jane", "-c", "user.email=jane@example.com"])
+                .args(["-c", "commit.gpgsign=false"])
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {args:?} failed: {output:?}");
+
+            String::from_utf8(output.stdout).unwrap()
+        }
+
+        /// Appends a line to `file` and commits it with `summary`.
+        fn commit(&self, file: &str, summary: &str) {
+            self.write(file, summary);
+            self.git(&["add", "--all"]);
+            self.git(&["commit", "--quiet", "--message", summary]);
+        }
+
+        fn write(&self, file: &str, line: &str) {
+            let path = self.repo().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let contents = fs::read_to_string(&path).unwrap_or_default();
+            fs::write(path, format!("{contents}{line}\n")).unwrap();
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn commit(hash: &str, date: &str, author: &str, summary: &str) -> Commit {
+        Commit::new(record(&[hash, date, author, summary])).unwrap()
+    }
+
+    fn summaries(commits: Vec<Commit>) -> Vec<String> {
+        let mut summaries: Vec<_> = commits.into_iter().map(|c| c.summary).collect();
+        summaries.sort();
+        summaries
+    }
+
+    fn record(fields: &[&str]) -> String {
+        fields.join(&FIELD_SEPARATOR.to_string())
+    }
+
+    fn output(stdout: &str) -> Output {
+        Output {
+            status: ExitStatus::default(),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parse_line_keeps_separators_the_options_didnt_ask_for() {
+        let summary = "colored \x1b[31mred\x1b[0m output \x1c\x18\x1a\x19\x17\x1d";
+        let line = record(&["abc1234", "2024-01-02", "Jane", summary]);
+
+        let commit = parse_line(&line, &Layout::new(&CompareOptions::new())).unwrap();
+
+        assert_eq!(commit.hash, "abc1234");
+        assert_eq!(commit.author_date, "2024-01-02");
+        assert_eq!(commit.author, "Jane");
+        assert_eq!(commit.summary, summary);
+        assert_eq!(commit.email, None);
+        assert!(commit.extra.is_empty());
+    }
+
+    #[test]
+    fn parse_line_splits_off_the_fields_after_the_summary() {
+        let opts = CompareOptions::new()
+            .exclude_authors(vec!["someone".to_string()])
+            .relative_dates(true)
+            .commit_dates(true)
+            .decorations(true)
+            .match_strategy(MatchStrategy::TreeHash)
+            .signatures(true)
+            .extra_placeholders(vec!["%h".to_string(), "%b".to_string()]);
+        let line = record(&["abc1234", "2024-01-02", "Jane", "Fix \x1b[1mbold\x1b[0m"])
+            + "\x1bjane@example.com\x1c3 days ago\x182024-01-03\x1aHEAD -> main, tag: v1"
+            + "\x19tree\x17G\x1dabc\x1fline one\nline two";
+
+        let commit = parse_line(&line, &Layout::new(&opts)).unwrap();
+
+        assert_eq!(commit.summary, "Fix \x1b[1mbold\x1b[0m");
+        assert_eq!(commit.email.as_deref(), Some("jane@example.com"));
+        assert_eq!(commit.relative_date.as_deref(), Some("3 days ago"));
+        assert_eq!(commit.commit_date.as_deref(), Some("2024-01-03"));
+        assert_eq!(commit.refs, ["HEAD", "main", "v1"]);
+        assert_eq!(commit.tree.as_deref(), Some("tree"));
+        assert_eq!(commit.signature, Some(SignatureStatus::Good));
+        assert_eq!(commit.extra, ["abc", "line one\nline two"]);
+    }
+
+    #[test]
+    fn parse_record_keeps_separators_in_the_body() {
+        let opts = CompareOptions::new().body(true);
+        let body = "First line \x1b[0m\n\nwith a \x1f unit separator\n";
+        let text = record(&["abc1234", "2024-01-02", "Jane", "Summary", body]);
+
+        let commit = parse_record(&text, &Layout::new(&opts)).unwrap();
+
+        assert_eq!(commit.summary, "Summary");
+        assert_eq!(
+            commit.body.as_deref(),
+            Some("First line \x1b[0m\n\nwith a \x1f unit separator")
+        );
+    }
+
+    #[test]
+    fn from_log_line_reads_the_body_before_the_suffixes() {
+        let opts = CompareOptions::new()
+            .body(true)
+            .exclude_authors(vec!["someone".to_string()])
+            .commit_dates(true);
+        let line = record(&[
+            "abc1234",
+            "2024-01-02",
+            "Jane",
+            "Summary",
+            "First\n\nSecond\n",
+        ]) + "\x1bjane@example.com\x182024-01-03";
+
+        let commit = Commit::from_log_line(&line, &opts).unwrap();
+
+        assert_eq!(commit.summary, "Summary");
+        assert_eq!(commit.body.as_deref(), Some("First\n\nSecond"));
+        assert_eq!(commit.email.as_deref(), Some("jane@example.com"));
+        assert_eq!(commit.commit_date.as_deref(), Some("2024-01-03"));
+    }
+
+    #[test]
+    fn split_suffixes_takes_off_the_requested_fields_from_the_end() {
+        let opts = CompareOptions::new()
+            .exclude_authors(vec!["someone".to_string()])
+            .match_strategy(MatchStrategy::TreeHash)
+            .extra_placeholders(vec!["%h".to_string()]);
+        let text = "Summary \x18 kept\x1bjane@example.com\x19tree\x1dabc";
+
+        let (text, suffixes) = split_suffixes(text, &Layout::new(&opts));
+
+        assert_eq!(text, "Summary \x18 kept");
+        assert_eq!(suffixes.email.as_deref(), Some("jane@example.com"));
+        assert_eq!(suffixes.tree.as_deref(), Some("tree"));
+        assert_eq!(suffixes.commit_date, None);
+        assert_eq!(suffixes.extra, ["abc"]);
+    }
+
+    #[test]
+    fn parse_record_without_a_body_reads_a_line() {
+        let text = record(&["abc1234", "2024-01-02", "Jane", "Summary"]);
+
+        let commit = parse_record(&text, &Layout::new(&CompareOptions::new())).unwrap();
+
+        assert_eq!(commit.summary, "Summary");
+        assert_eq!(commit.body, None);
+        assert!(parse_record("abc1234", &Layout::new(&CompareOptions::new())).is_none());
+    }
+
+    #[test]
+    fn parse_git_output_splits_records_first() {
+        let opts = CompareOptions::new()
+            .numstat(true)
+            .extra_placeholders(vec!["%b".to_string()]);
+        let first = record(&["abc1234", "2024-01-02", "Jane", "First"]);
+        let second = record(&["def5678", "2024-01-01", "John", "Second"]);
+        let stdout = format!(
+            "\0{first}\x1dtwo\nlines\0\n\n1\t2\tsrc/a.rs\n3\t0\tsrc/b.rs\n\0{second}\x1d\0\n\n{}",
+            "4\t4\tREADME.md\n"
+        );
+
+        let commits = parse_git_output(output(&stdout), &opts);
+
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].summary, "First");
+        assert_eq!(commits[0].extra, ["two\nlines"]);
+        assert_eq!((commits[0].additions, commits[0].deletions), (4, 2));
+        assert_eq!(commits[0].files, ["src/a.rs", "src/b.rs"]);
+        assert_eq!(commits[1].summary, "Second");
+        assert_eq!(commits[1].extra, [""]);
+        assert_eq!(commits[1].files, ["README.md"]);
+    }
+
+    #[test]
+    fn parse_git_output_uses_the_configured_separators() {
+        let opts = CompareOptions::new()
+            .record_separator('\x01')
+            .field_separator('\x02')
+            .body(true);
+        let stdout = concat!(
+            "\x01abc1234\x022024-01-02\x02Jane\x02Has \x1f and \0\x02Body\x01\n",
+            "\x01def5678\x022024-01-01\x02John\x02Second\x02\x01",
+        );
+
+        let commits = parse_git_output(output(stdout), &opts);
+
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].summary, "Has \x1f and \0");
+        assert_eq!(commits[0].body.as_deref(), Some("Body"));
+        assert_eq!(commits[1].summary, "Second");
+        assert_eq!(commits[1].body, None);
+    }
+
+    #[test]
+    fn anonymize_tells_namesakes_apart_by_email() {
+        let opts = CompareOptions::new().anonymize_authors(true);
+        let layout = Layout::new(&opts);
+        let mut commits: Vec<_> = ["jane@example.com", "jane@example.org", "JANE@example.com"]
+            .iter()
+            .map(|email| {
+                let line = record(&["abc1234", "2024-01-02", "Jane", "Summary"]) + "\x1b" + email;
+                parse_line(&line, &layout).unwrap()
+            })
+            .collect();
+
+        anonymize(&mut commits, &Pseudonyms::default(), &opts);
+
+        let authors: Vec<_> = commits
+            .iter()
+            .map(|commit| commit.author.as_str())
+            .collect();
+        assert_eq!(authors, ["author-1", "author-2", "author-1"]);
+        assert!(commits.iter().all(|commit| commit.email.is_none()));
+    }
+
+    #[test]
+    fn ensure_threshold_rejects_thresholds_out_of_range() {
+        for threshold in [-0.1, 1.5, f64::NAN] {
+            let opts = CompareOptions::new().match_strategy(MatchStrategy::Fuzzy { threshold });
+
+            assert!(matches!(
+                ensure_threshold(&opts),
+                Err(DiffError::InvalidThreshold(_))
+            ));
+        }
+
+        for threshold in [0.0, 0.8, 1.0] {
+            let opts = CompareOptions::new().match_strategy(MatchStrategy::Fuzzy { threshold });
+
+            assert!(ensure_threshold(&opts).is_ok());
+        }
+    }
+
+    #[test]
+    fn ensure_separators_rejects_bytes_the_output_uses() {
+        for opts in [
+            CompareOptions::new().record_separator('a'),
+            CompareOptions::new().record_separator('\n'),
+            CompareOptions::new().field_separator(EMAIL_SEPARATOR),
+            CompareOptions::new().field_separator(RECORD_SEPARATOR),
+        ] {
+            assert!(matches!(
+                ensure_separators(&opts),
+                Err(DiffError::InvalidSeparator(_))
+            ));
+        }
+
+        assert!(ensure_separators(&CompareOptions::new()).is_ok());
+    }
+
+    #[test]
+    fn compares_a_branch_against_a_stash_entry() {
+        let fixture = Fixture::new("stash");
+        fixture.write("README.md", "A stashed line");
+        fixture.git(&["stash", "push", "--quiet"]);
+        let head = fixture.git(&["rev-parse", "--short", "HEAD"]);
+        let opts = CompareOptions::new().repo_path(fixture.repo());
+
+        let stashed = compare_branches_with("stash@{0}", "main", &opts).unwrap();
+        let unstashed = compare_branches_with("main", "stash@{0}", &opts).unwrap();
+
+        assert_eq!(
+            summaries(stashed),
+            [
+                format!("WIP on main: {} Add the readme", head.trim()),
+                format!("index on main: {} Add the readme", head.trim()),
+            ]
+        );
+        assert!(unstashed.is_empty());
+    }
+
+    #[test]
+    fn compares_from_a_subdirectory_with_paths_relative_to_it() {
+        let fixture = Fixture::new("subdirectory");
+        fixture.commit("src/main.rs", "Add the binary");
+        fixture.git(&["switch", "--quiet", "--create", "feature"]);
+        fixture.commit("src/lib.rs", "Add the library");
+        fixture.commit("README.md", "Document the library");
+        let opts = CompareOptions::new().repo_path(fixture.repo().join("src"));
+
+        let all = compare_branches_with("feature", "main", &opts).unwrap();
+        let in_dir =
+            compare_branches_with("feature", "main", &opts.clone().paths(vec![".".into()]));
+
+        assert_eq!(summaries(all), ["Add the library", "Document the library"]);
+        assert_eq!(summaries(in_dir.unwrap()), ["Add the library"]);
+    }
+
+    #[test]
+    fn compares_the_head_of_a_linked_worktree() {
+        let fixture = Fixture::new("worktree");
+        let worktree = fixture.root.join("worktree");
+        let worktree_arg = worktree.to_string_lossy();
+        fixture.git(&["worktree", "add", "--quiet", "-b", "feature", &worktree_arg]);
+        fs::write(worktree.join("feature.txt"), "A feature\n").unwrap();
+        fixture.git(&["-C", &worktree_arg, "add", "--all"]);
+        fixture.git(&[
+            "-C",
+            &worktree_arg,
+            "commit",
+            "--quiet",
+            "--message",
+            "Add a feature",
+        ]);
+        let in_worktree = CompareOptions::new().repo_path(&worktree);
+        let in_main = CompareOptions::new().repo_path(fixture.repo());
+
+        let from_worktree = compare_branches_with("HEAD", "main", &in_worktree).unwrap();
+        let from_main = compare_branches_with("feature", "HEAD", &in_main).unwrap();
+
+        assert_eq!(summaries(from_worktree), ["Add a feature"]);
+        assert_eq!(summaries(from_main), ["Add a feature"]);
+        assert!(compare_branches_with("HEAD", "feature", &in_main)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn cherry_mark_bounds_the_author_dates() {
+        let fixture = Fixture::new("cherry-mark-dates");
+        fixture.git(&["switch", "--quiet", "--create", "feature"]);
+        fixture.write("old.txt", "An old change");
+        fixture.git(&["add", "--all"]);
+        fixture.git(&[
+            "commit",
+            "--quiet",
+            "--date=2020-01-01T00:00:00",
+            "-m",
+            "Add an old change",
+        ]);
+        fixture.commit("new.txt", "Add a new change");
+        let opts = CompareOptions::new()
+            .repo_path(fixture.repo())
+            .match_strategy(MatchStrategy::CherryMark)
+            .date_kind(DateKind::Author)
+            .since("2021-01-01");
+
+        let commits = compare_branches_with("feature", "main", &opts).unwrap();
+
+        assert_eq!(summaries(commits.clone()), ["Add a new change"]);
+        assert!(commits[0].extra.is_empty());
+    }
+
+    #[test]
+    fn compare_branches_iter_reads_the_bodies() {
+        let fixture = Fixture::new("iter-bodies");
+        fixture.git(&["switch", "--quiet", "--create", "feature"]);
+        fixture.write("fix.txt", "A fix");
+        fixture.git(&["add", "--all"]);
+        fixture.git(&[
+            "commit",
+            "--quiet",
+            "-m",
+            "Fix the parser",
+            "-m",
+            "Fixes: #12\nand more",
+        ]);
+        fixture.commit("other.txt", "Add another change");
+        let opts = CompareOptions::new()
+            .repo_path(fixture.repo())
+            .body_contains("Fixes: #12");
+
+        let streamed: Vec<_> = compare_branches_iter("feature", "main", &opts)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].summary, "Fix the parser");
+        assert_eq!(streamed[0].body.as_deref(), Some("Fixes: #12\nand more"));
+    }
+
+    #[test]
+    fn paths_no_commit_touches_match_nothing() {
+        let fixture = Fixture::new("untouched-paths");
+        fixture.git(&["switch", "--quiet", "--create", "feature"]);
+        fixture.commit("src/lib.rs", "Add the library");
+        let opts = CompareOptions::new()
+            .repo_path(fixture.repo())
+            .paths(vec!["missing.txt".into()]);
+
+        let commits = compare_branches_with("feature", "main", &opts).unwrap();
+
+        assert!(commits.is_empty());
+    }
+
+    #[test]
+    fn narrow_drops_the_excluded_commits_before_deduping() {
+        let opts = CompareOptions::new()
+            .dedupe(true)
+            .exclude_authors(vec!["bot".to_string()]);
+        let filter = CommitFilter::new(".", &opts).unwrap();
+        let commits = vec![
+            commit("c3", "2024-03-01", "bot", "Bump the version"),
+            commit("c2", "2024-02-01", "Jane", "Bump the version"),
+            commit("c1", "2024-01-01", "Jane", "Add the parser"),
+        ];
+
+        let hashes: Vec<_> = narrow(commits, &filter, &opts)
+            .into_iter()
+            .map(|commit| commit.hash)
+            .collect();
+
+        assert_eq!(hashes, ["c2", "c1"]);
+    }
+
+    #[test]
+    fn narrow_keeps_the_target_of_an_excluded_revert() {
+        let opts = CompareOptions::new()
+            .collapse_reverts(1)
+            .exclude(vec!["Revert".to_string()]);
+        let filter = CommitFilter::new(".", &opts).unwrap();
+        let commits = vec![
+            commit("c2", "2024-02-01", "Jane", "Revert \"Add the parser\""),
+            commit("c1", "2024-01-01", "Jane", "Add the parser"),
+        ];
+
+        assert_eq!(
+            summaries(narrow(commits, &filter, &opts)),
+            ["Add the parser"]
+        );
+    }
+
+    #[cfg(feature = "git2")]
+    #[test]
+    fn revwalk_lists_same_second_commits_like_git_log() {
+        let fixture = Fixture::new("same-second");
+        let date = Some("2030-01-02T03:04:05+00:00");
+        let commit = |file: &str, summary: &str| {
+            fixture.write(file, summary);
+            fixture.git_at(date, &["add", "--all"]);
+            fixture.git_at(date, &["commit", "--quiet", "-m", summary]);
+        };
+        fixture.git(&["switch", "--quiet", "--create", "feature"]);
+        commit("a.txt", "dup");
+        commit("b.txt", "ABC-1");
+        commit("c.txt", "token");
+        fixture.git(&["switch", "--quiet", "main"]);
+        commit("d.txt", "feat");
+        commit("e.txt", "dup");
+        fixture.git_at(date, &["merge", "--quiet", "--no-edit", "feature"]);
+        let repo = fixture.repo();
+        let repo = repo.to_string_lossy();
+        let opts = CompareOptions::new();
+
+        let walked = revwalk::branch_commits(&repo, "main", &opts).unwrap();
+        let logged = parse_git_output(get_branch_commits(&repo, "main", &opts).unwrap(), &opts);
+
+        let hashes = |commits: Vec<Commit>| -> Vec<String> {
+            commits.into_iter().map(|commit| commit.hash).collect()
+        };
+        assert_eq!(hashes(walked), hashes(logged));
+    }
+
+    #[test]
+    fn require_clean_counts_the_changed_tracked_files() {
+        let fixture = Fixture::new("dirty");
+        fixture.write("README.md", "An uncommitted line");
+        fixture.write("untracked.txt", "An untracked file");
+        let opts = CompareOptions::new()
+            .repo_path(fixture.repo())
+            .require_clean(true);
+
+        let err = compare_branches_with("main", "main", &opts).unwrap_err();
+
+        assert!(matches!(err, DiffError::DirtyWorkingTree(1)));
+        assert_eq!(
+            err.to_string(),
+            "1 tracked file has uncommitted changes; commit or stash it first"
+        );
+    }
+
+    #[test]
+    fn get_patch_ids_reports_what_git_log_wrote() {
+        let fixture = Fixture::new("patch-id-error");
+        let repo = fixture.repo();
+
+        let err = get_patch_ids(
+            &repo.to_string_lossy(),
+            "no-such-ref",
+            &CompareOptions::new(),
+        );
+
+        let Err(DiffError::GitFailed { stderr, .. }) = err else {
+            panic!("expected a git failure, got {err:?}");
+        };
+        assert!(stderr.contains("no-such-ref"), "{stderr}");
+    }
+
+    #[test]
+    fn summary_key_applies_the_matching_options() {
+        let summary = "feat(parser):  Read   the  bodies";
+
+        assert_eq!(summary_key(summary, &CompareOptions::new()), summary);
+        assert_eq!(
+            summary_key(summary, &CompareOptions::new().normalize_whitespace(true)),
+            "feat(parser): Read the bodies"
+        );
+        assert_eq!(
+            summary_key(
+                summary,
+                &CompareOptions::new()
+                    .ignore_conventional_prefix(true)
+                    .normalize_whitespace(true)
+            ),
+            "Read the bodies"
+        );
+        assert_eq!(
+            summary_key(
+                summary,
+                &CompareOptions::new().key_prefix(KeyPrefix::Length(4))
+            ),
+            "feat"
+        );
+        assert_eq!(
+            summary_key(
+                summary,
+                &CompareOptions::new().key_prefix(KeyPrefix::Delimiter(":".to_string()))
+            ),
+            "feat(parser)"
+        );
+        assert_eq!(
+            summary_key(
+                "No delimiter",
+                &CompareOptions::new().key_prefix(KeyPrefix::Delimiter(":".to_string()))
+            ),
+            "No delimiter"
+        );
+    }
+
+    #[test]
+    fn levenshtein_counts_the_edits() {
+        let chars = |text: &str| text.chars().collect::<Vec<_>>();
+
+        assert_eq!(levenshtein(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(levenshtein(&chars(""), &chars("abc")), 3);
+        assert_eq!(levenshtein(&chars("abc"), &chars("abc")), 0);
+        assert_eq!(levenshtein(&chars("héllo"), &chars("hello")), 1);
+    }
+
+    #[test]
+    fn similarity_is_normalized_by_the_longer_summary() {
+        let chars = |text: &str| text.chars().collect::<Vec<_>>();
+
+        assert_eq!(similarity(&chars(""), &chars("")), 1.0);
+        assert_eq!(similarity(&chars("abcd"), &chars("abcd")), 1.0);
+        assert_eq!(similarity(&chars("abcd"), &chars("abce")), 0.75);
+        assert_eq!(similarity(&chars("abcd"), &chars("")), 0.0);
+    }
+
+    #[test]
+    fn subtract_fuzzy_drops_the_commits_close_enough_to_one_of_the_other_ref() {
+        let commits1 = vec![
+            commit("c2", "2024-01-02", "Jane", "Fix the typo in the parser"),
+            commit("c1", "2024-01-01", "Jane", "Add the formatter"),
+        ];
+        let commits2 = [commit(
+            "d1",
+            "2024-01-03",
+            "Jane",
+            "Fix the typos in the parser",
+        )];
+
+        let unique = subtract_fuzzy(commits1, &commits2, 0.9, &CompareOptions::new());
+
+        assert_eq!(summaries(unique), ["Add the formatter"]);
+    }
+
+    #[test]
+    fn exclusions_match_whole_words_only_when_asked() {
+        let words = vec!["fix".to_string(), "  ".to_string()];
+        let substrings = Exclusions::new(&words, ExcludeMode::Substring, false, false).unwrap();
+        let whole_words = Exclusions::new(&words, ExcludeMode::Substring, true, true).unwrap();
+
+        assert!(substrings.matches("Add a prefix"));
+        assert!(!whole_words.matches("Add a prefix"));
+        assert!(whole_words.matches("FIX: the parser"));
+        assert!(whole_words.matches("A quick fix"));
+        assert!(whole_words.matches("Use a (fix)."));
+        assert!(!substrings.matches("Nothing to see"));
+    }
+
+    #[test]
+    fn exclusions_reject_invalid_patterns() {
+        let words = vec!["(unclosed".to_string()];
+
+        assert!(matches!(
+            Exclusions::new(&words, ExcludeMode::Regex, false, false),
+            Err(DiffError::InvalidPattern { .. })
+        ));
+        assert!(Exclusions::new(&words, ExcludeMode::Substring, false, true).is_ok());
+    }
+
+    #[test]
+    fn collapse_reverts_pairs_reverts_up_to_the_depth() {
+        let commits = || {
+            vec![
+                commit(
+                    "c4",
+                    "2024-01-04",
+                    "Jane",
+                    "Revert \"Revert \"Add the parser\"\"",
+                ),
+                commit("c3", "2024-01-03", "Jane", "Revert \"Add the parser\""),
+                commit("c2", "2024-01-02", "Jane", "Add the parser"),
+                commit("c1", "2024-01-01", "Jane", "Revert \"Add the formatter\""),
+            ]
+        };
+        let hashes = |commits: Vec<Commit>| -> Vec<String> {
+            commits.into_iter().map(|commit| commit.hash).collect()
+        };
+
+        assert_eq!(
+            hashes(collapse_reverts(commits(), 0)),
+            ["c4", "c3", "c2", "c1"]
+        );
+        assert_eq!(hashes(collapse_reverts(commits(), 1)), ["c4", "c1"]);
+        assert_eq!(hashes(collapse_reverts(commits(), 2)), ["c2", "c1"]);
+    }
+
+    #[test]
+    fn dedupe_keeps_the_latest_commit_of_each_summary() {
+        let commits = vec![
+            commit("c4", "2024-01-02", "Jane", "Bump the version"),
+            commit("c3", "2024-01-03", "Jane", "Bump the version"),
+            commit("c2", "2024-01-01", "Jane", "Add the parser"),
+            commit("c1", "2024-01-01", "Jane", "Add the parser"),
+        ];
+
+        let hashes: Vec<_> = dedupe(commits, &CompareOptions::new())
+            .into_iter()
+            .map(|commit| commit.hash)
+            .collect();
+
+        assert_eq!(hashes, ["c3", "c2"]);
+    }
 }
@@ -0,0 +1,7 @@
+pub mod analysis;
+pub mod error;
+pub mod format;
+pub mod git;
+pub mod options;
+#[cfg(feature = "git2")]
+mod revwalk;
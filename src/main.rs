@@ -1,28 +1,276 @@
 use std::process;
+use std::time::Duration;
 use structopt::StructOpt;
 
+use fdiff::analysis;
+use fdiff::format;
+use fdiff::git;
+use fdiff::options::{
+    self, CompareOptions, DateKind, ExcludeMode, KeyPrefix, MatchStrategy, MergeFilter, SortOrder,
+    Verbosity,
+};
+
 mod cli;
-mod git;
 
 fn main() {
     let args = cli::CliArgs::from_args();
 
-    let branch1 = args.branch1;
-    let branch2 = args.branch2;
-    let exclude = args.exclude;
-    let repo = args.repo_path;
+    let exclude_mode = if args.regex {
+        ExcludeMode::Regex
+    } else {
+        ExcludeMode::Substring
+    };
+    let match_strategy = if args.patch_id {
+        MatchStrategy::PatchId
+    } else if args.change_id {
+        MatchStrategy::ChangeId
+    } else if args.cherry_mark {
+        MatchStrategy::CherryMark
+    } else if args.tree_hash {
+        MatchStrategy::TreeHash
+    } else if let Some(threshold) = args.fuzzy {
+        MatchStrategy::Fuzzy { threshold }
+    } else {
+        MatchStrategy::Summary
+    };
+
+    let mut exclude = options::env_excludes();
+    exclude.extend(args.exclude.unwrap_or_default());
+    if let Some(exclude_file) = args.exclude_file {
+        match options::load_excludes(&exclude_file) {
+            Ok(entries) => exclude.extend(entries),
+            Err(e) => {
+                eprintln!("Error: couldn't read '{}': {e}", exclude_file.display());
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut opts = CompareOptions::new()
+        .exclude(exclude)
+        .include(args.include.unwrap_or_default())
+        .exclude_authors(args.exclude_author.unwrap_or_default())
+        .exclude_mine(args.exclude_mine)
+        .anonymize_authors(args.anonymize)
+        .redact_secrets(args.redact)
+        .exclude_mode(exclude_mode)
+        .case_insensitive(args.ignore_case)
+        .whole_word(args.whole_word)
+        .match_strategy(match_strategy)
+        .divergent_only(args.divergent_only)
+        .normalize_whitespace(args.ignore_whitespace)
+        .ignore_conventional_prefix(args.ignore_type_prefix)
+        .first_parent(args.first_parent)
+        .verbose(args.verbose)
+        .retries(args.retries)
+        .fetch_first(args.fetch)
+        .require_clean(args.require_clean)
+        .relative_dates(args.relative_date)
+        .dedupe(args.dedupe)
+        .skip_merge_summaries(args.skip_merge_summaries)
+        .allow_unrelated(args.allow_unrelated)
+        .reject_detached_head(args.reject_detached)
+        .since_merge_base(args.since_merge_base)
+        .detect_squashes(args.detect_squashes)
+        .full_hash(args.full_hash)
+        .decorations(args.refs)
+        .paths(args.paths)
+        .follow(args.follow)
+        .include_types(args.types);
+
+    if let Some(repo_path) = args.repo_path {
+        opts = opts.repo_path(repo_path);
+    }
+    if let Some(since) = args.since {
+        opts = opts.since(since);
+    }
+    if let Some(until) = args.until {
+        opts = opts.until(until);
+    }
+    if args.quiet {
+        opts = opts.verbosity(Verbosity::Quiet);
+    }
+    if args.author_date {
+        opts = opts.date_kind(DateKind::Author);
+    }
+    if let Some(body_contains) = args.body_contains {
+        opts = opts.body_contains(body_contains);
+    }
+    if let Some(author) = args.author {
+        opts = opts.author(author);
+    }
+    if let Some(max_count) = args.max_count {
+        opts = opts.max_count(max_count);
+    }
+    if let Some(skip) = args.skip {
+        opts = opts.skip(skip);
+    }
+    if let Some(min_files) = args.min_files {
+        opts = opts.min_files(min_files);
+    }
+    if let Some(min_lines) = args.min_lines {
+        opts = opts.min_changed_lines(min_lines);
+    }
+    if let Some(git_binary) = args.git_binary {
+        opts = opts.git_binary(git_binary);
+    }
+    if let Some(timeout) = args.timeout {
+        opts = opts.timeout(Duration::from_secs(timeout));
+    }
+    if args.no_merges {
+        opts = opts.merge_filter(MergeFilter::NoMerges);
+    } else if args.merges {
+        opts = opts.merge_filter(MergeFilter::OnlyMerges);
+    }
+    if let Some(length) = args.key_length {
+        opts = opts.key_prefix(KeyPrefix::Length(length));
+    }
+    if let Some(delimiter) = args.key_delimiter {
+        opts = opts.key_prefix(KeyPrefix::Delimiter(delimiter));
+    }
+    if args.collapse_reverts {
+        opts = opts.collapse_reverts(1);
+    }
+    if args.oldest_first {
+        opts = opts.sort_order(SortOrder::Ascending);
+    } else if args.newest_first {
+        opts = opts.sort_order(SortOrder::Descending);
+    }
+
+    if args.dry_run {
+        match git::validate(&args.ref1, &args.ref2, &opts) {
+            Ok(()) => println!(
+                "The comparison of {} and {} is valid",
+                label(&args.ref1, &opts),
+                label(&args.ref2, &opts)
+            ),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.count {
+        match git::ahead_behind(&args.ref1, &args.ref2, &opts) {
+            Ok((ahead, behind)) => println!(
+                "{} is {ahead} ahead, {behind} behind {}",
+                label(&args.ref1, &opts),
+                label(&args.ref2, &opts)
+            ),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.left_right {
+        match git::symmetric_diff(&args.ref1, &args.ref2, &opts) {
+            Ok(commits) => {
+                for (side, commit) in commits {
+                    let marker = match side {
+                        git::Side::Left => '<',
+                        git::Side::Right => '>',
+                    };
+                    println!("{marker} {}: {}", commit.display_date(), commit.summary);
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let result = git::compare_branches(&branch1, &branch2, exclude, repo);
+    let result = git::compare_branches_with(&args.ref1, &args.ref2, &opts);
 
     match result {
-        Ok(commits) => {
-            for commit in commits {
-                println!("{}: {}", commit.date, commit.summary);
+        #[cfg(feature = "serde")]
+        Ok(commits) if args.json => match format::to_json(&commits) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        },
+        #[cfg(feature = "serde")]
+        Ok(commits) if args.ndjson => match format::to_ndjson(&commits) {
+            Ok(ndjson) => print!("{ndjson}"),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        },
+        #[cfg(feature = "csv")]
+        Ok(commits) if args.csv => match format::to_csv(&commits) {
+            Ok(csv) => print!("{csv}"),
+            Err(e) => {
+                eprintln!("Error: {e}");
+                process::exit(1);
+            }
+        },
+        Ok(commits) if args.summaries_only => {
+            for summary in format::summaries_only(&commits) {
+                println!("{summary}");
             }
         }
+        Ok(commits) if args.markdown => print!("{}", format::to_markdown_table(&commits)),
+        Ok(commits) if args.html => {
+            let title = format!(
+                "Commits in {} that aren't in {}",
+                label(&args.ref1, &opts),
+                label(&args.ref2, &opts)
+            );
+            print!("{}", format::to_html(&commits, &title));
+        }
+        Ok(commits) if args.stats => {
+            print_stats(&commits);
+            print_commits(&commits);
+        }
+        Ok(commits) => print_commits(&commits),
         Err(e) => {
             eprintln!("Error: {e}");
             process::exit(1);
         }
     }
 }
+
+// Shows `HEAD` with the branch or commit it's at, so it's clear what was compared.
+fn label(ref_: &str, opts: &CompareOptions) -> String {
+    git::resolve_ref(ref_, opts)
+        .map(|resolved| resolved.to_string())
+        .unwrap_or_else(|_| ref_.to_string())
+}
+
+fn print_stats(commits: &[git::Commit]) {
+    let stats = analysis::stats(commits);
+
+    match (stats.earliest, stats.latest) {
+        (Some(earliest), Some(latest)) => println!(
+            "{} commits by {} authors, from {earliest} to {latest}",
+            stats.total, stats.authors
+        ),
+        _ => println!("No commits"),
+    }
+}
+
+#[cfg(feature = "color")]
+fn print_commits(commits: &[git::Commit]) {
+    format::print_colored(commits);
+}
+
+#[cfg(not(feature = "color"))]
+fn print_commits(commits: &[git::Commit]) {
+    for commit in commits {
+        println!(
+            "{}: {}{}",
+            commit.display_date(),
+            commit.summary,
+            format::annotations(commit)
+        );
+    }
+}
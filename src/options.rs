@@ -0,0 +1,958 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::DiffError;
+
+/// How two commits from different branches are decided to be "the same".
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MatchStrategy {
+    /// Commits match when their summaries are equal.
+    #[default]
+    Summary,
+    /// Commits match when their diffs are equal, as computed by `git patch-id`.
+    /// Commits without a patch (merges, empty commits) fall back to the summary.
+    PatchId,
+    /// Commits match when the normalized Levenshtein similarity of their summaries, between `0.0`
+    /// and `1.0`, is at least `threshold`, itself between `0.0` and `1.0` or the comparison fails
+    /// with `DiffError::InvalidThreshold`. Every summary is compared against every summary of
+    /// the other ref, so this is much slower than the other strategies on long histories.
+    Fuzzy { threshold: f64 },
+    /// Commits match when they carry the same `Change-Id:` trailer, as Gerrit adds to every
+    /// commit and keeps across cherry-picks. Commits without one fall back to the summary.
+    ChangeId,
+    /// Commits match when `git log --cherry-mark` marks them as equivalent, which compares their
+    /// patch ids like `PatchId` but in a single git command. Only the commits the refs don't
+    /// share are read, and `max_count` applies to them together. Comparing across repositories
+    /// falls back to the summary.
+    CherryMark,
+    /// Commits match when they have the same tree, that is when they leave the files in the same
+    /// state whatever their parents, such as a branch recreated from scratch. Works across
+    /// repositories too.
+    TreeHash,
+}
+
+/// Parses the names `Display` gives the strategies: `summary`, `patch-id`, `change-id`,
+/// `cherry-mark`, `tree-hash`, and `fuzzy:<threshold>` such as `fuzzy:0.8`.
+impl FromStr for MatchStrategy {
+    type Err = ParseOptionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "summary" => Ok(Self::Summary),
+            "patch-id" => Ok(Self::PatchId),
+            "change-id" => Ok(Self::ChangeId),
+            "cherry-mark" => Ok(Self::CherryMark),
+            "tree-hash" => Ok(Self::TreeHash),
+            _ => value
+                .strip_prefix("fuzzy:")
+                .and_then(|threshold| threshold.parse().ok())
+                .filter(|threshold| (0.0..=1.0).contains(threshold))
+                .map(|threshold| Self::Fuzzy { threshold })
+                .ok_or_else(|| {
+                    ParseOptionError::new(
+                        value,
+                        &[
+                            "summary",
+                            "patch-id",
+                            "change-id",
+                            "cherry-mark",
+                            "tree-hash",
+                            "fuzzy:<threshold>",
+                        ],
+                    )
+                }),
+        }
+    }
+}
+
+impl fmt::Display for MatchStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Summary => f.write_str("summary"),
+            Self::PatchId => f.write_str("patch-id"),
+            Self::ChangeId => f.write_str("change-id"),
+            Self::CherryMark => f.write_str("cherry-mark"),
+            Self::TreeHash => f.write_str("tree-hash"),
+            Self::Fuzzy { threshold } => write!(f, "fuzzy:{threshold}"),
+        }
+    }
+}
+
+/// The part of the summaries commits are matched by, such as a leading ticket id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyPrefix {
+    /// The first characters of the summary.
+    Length(usize),
+    /// The summary up to the first occurrence of the delimiter, or the whole summary without it.
+    Delimiter(String),
+}
+
+/// Parses the forms `Display` gives the prefixes: `length:<count>` such as `length:8`, and
+/// `delimiter:<text>` such as `delimiter::`.
+impl FromStr for KeyPrefix {
+    type Err = ParseOptionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(length) = value.strip_prefix("length:") {
+            if let Ok(length) = length.parse() {
+                return Ok(Self::Length(length));
+            }
+        } else if let Some(delimiter) = value.strip_prefix("delimiter:") {
+            if !delimiter.is_empty() {
+                return Ok(Self::Delimiter(delimiter.to_string()));
+            }
+        }
+
+        Err(ParseOptionError::new(
+            value,
+            &["length:<count>", "delimiter:<text>"],
+        ))
+    }
+}
+
+impl fmt::Display for KeyPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length(length) => write!(f, "length:{length}"),
+            Self::Delimiter(delimiter) => write!(f, "delimiter:{delimiter}"),
+        }
+    }
+}
+
+/// How the exclude and include entries are matched against the commit summaries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExcludeMode {
+    /// Match the summaries that contain the entry.
+    #[default]
+    Substring,
+    /// Match the summaries that match the entry as a regular expression.
+    Regex,
+}
+
+impl FromStr for ExcludeMode {
+    type Err = ParseOptionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "substring" => Ok(Self::Substring),
+            "regex" => Ok(Self::Regex),
+            _ => Err(ParseOptionError::new(value, &["substring", "regex"])),
+        }
+    }
+}
+
+impl fmt::Display for ExcludeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Substring => "substring",
+            Self::Regex => "regex",
+        })
+    }
+}
+
+/// Which date of the commits the `since` and `until` options apply to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DateKind {
+    /// When the commit was made, which rebases and cherry-picks renew, like `git log --since`.
+    #[default]
+    Commit,
+    /// When the change was first authored, which rebases and cherry-picks keep.
+    Author,
+}
+
+impl FromStr for DateKind {
+    type Err = ParseOptionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "commit" => Ok(Self::Commit),
+            "author" => Ok(Self::Author),
+            _ => Err(ParseOptionError::new(value, &["commit", "author"])),
+        }
+    }
+}
+
+impl fmt::Display for DateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Commit => "commit",
+            Self::Author => "author",
+        })
+    }
+}
+
+/// The order the returned commits are sorted by date.
+///
+/// Dates are formatted as `%Y-%m-%d`, so they are sorted as plain strings. Commits sharing a
+/// date keep the order git listed them in, unless `break_ties_by_hash` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Oldest commits first.
+    Ascending,
+    /// Newest commits first.
+    Descending,
+}
+
+impl FromStr for SortOrder {
+    type Err = ParseOptionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ascending" => Ok(Self::Ascending),
+            "descending" => Ok(Self::Descending),
+            _ => Err(ParseOptionError::new(value, &["ascending", "descending"])),
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Ascending => "ascending",
+            Self::Descending => "descending",
+        })
+    }
+}
+
+/// Which commits are listed depending on whether they are merges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeFilter {
+    /// List every commit.
+    #[default]
+    All,
+    /// Leave out the merge commits, like `git log --no-merges`.
+    NoMerges,
+    /// Only list the merge commits, like `git log --merges`.
+    OnlyMerges,
+}
+
+impl FromStr for MergeFilter {
+    type Err = ParseOptionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(Self::All),
+            "no-merges" => Ok(Self::NoMerges),
+            "only-merges" => Ok(Self::OnlyMerges),
+            _ => Err(ParseOptionError::new(
+                value,
+                &["all", "no-merges", "only-merges"],
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MergeFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::All => "all",
+            Self::NoMerges => "no-merges",
+            Self::OnlyMerges => "only-merges",
+        })
+    }
+}
+
+/// How much a comparison prints to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Print nothing, except the dates the `chrono` feature couldn't parse, which `Commit::new`
+    /// warns about on its own.
+    Quiet,
+    /// Print the warnings, such as the ones git prints or a history cut short by `max_count`.
+    #[default]
+    Normal,
+    /// Also print every git command before running it, and how many commits were read and how
+    /// many of them the filters left out.
+    Verbose,
+}
+
+impl FromStr for Verbosity {
+    type Err = ParseOptionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "quiet" => Ok(Self::Quiet),
+            "normal" => Ok(Self::Normal),
+            "verbose" => Ok(Self::Verbose),
+            _ => Err(ParseOptionError::new(
+                value,
+                &["quiet", "normal", "verbose"],
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Verbosity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Quiet => "quiet",
+            Self::Normal => "normal",
+            Self::Verbose => "verbose",
+        })
+    }
+}
+
+/// The error of parsing an option from a string, such as an unknown `MatchStrategy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptionError {
+    value: String,
+    expected: &'static [&'static str],
+}
+
+impl ParseOptionError {
+    fn new(value: &str, expected: &'static [&'static str]) -> Self {
+        Self {
+            value: value.to_string(),
+            expected,
+        }
+    }
+}
+
+impl fmt::Display for ParseOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid value '{}', expected one of: {}",
+            self.value,
+            self.expected.join(", ")
+        )
+    }
+}
+
+impl Error for ParseOptionError {}
+
+/// What a comparison reports to the `progress` callback as it goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// The commits of `revision` were read and parsed.
+    Fetched { revision: String, commits: usize },
+    /// The fetched commits of both refs, `commits` in all, were matched against each other.
+    Compared { commits: usize },
+}
+
+/// The callback of the `progress` option.
+#[derive(Clone)]
+pub(crate) struct Progress(Arc<dyn Fn(ProgressEvent) + Send + Sync>);
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Progress(..)")
+    }
+}
+
+/// The patterns `skip_merge_summaries` recognizes, for the summaries git, GitHub, GitLab and
+/// Bitbucket give merges.
+pub const DEFAULT_MERGE_SUMMARY_PATTERNS: &[&str] = &[
+    r"^Merge pull request #\d+ from ",
+    r"^Merge branch '[^']+'",
+    r"^Merge remote-tracking branch '",
+    r"^Merge tag '",
+    r"^Merge commit '",
+    r"^Merged in \S+ \(pull request #\d+\)",
+];
+
+/// The patterns `redact_secrets` masks, for the shapes of common access tokens, keys and
+/// passwords, and URLs with credentials.
+pub const DEFAULT_REDACTION_PATTERNS: &[&str] = &[
+    r"\bgh[pousr]_[A-Za-z0-9]{36,}",
+    r"\bgithub_pat_[A-Za-z0-9_]{22,}",
+    r"\bglpat-[A-Za-z0-9_-]{20,}",
+    r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+    r"\bxox[abprs]-[A-Za-z0-9-]{10,}",
+    r"\beyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+",
+    r"-----BEGIN [A-Z ]*PRIVATE KEY-----",
+    r"(?i)\b(?:password|passwd|secret|token|api[_-]?key)\s*[:=]\s*\S+",
+    r"\b[a-z][a-z0-9+.-]*://[^/\s:@]+:[^/\s@]+@",
+];
+
+/// The options of a comparison. The defaults compare every commit of both refs by summary in
+/// the repository of the current directory.
+#[derive(Debug, Clone, Default)]
+pub struct CompareOptions {
+    pub(crate) exclude: Vec<String>,
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude_mode: ExcludeMode,
+    pub(crate) case_insensitive: bool,
+    pub(crate) whole_word: bool,
+    pub(crate) repo_path: Option<PathBuf>,
+    pub(crate) match_strategy: MatchStrategy,
+    pub(crate) since: Option<String>,
+    pub(crate) until: Option<String>,
+    pub(crate) date_kind: DateKind,
+    pub(crate) author: Option<String>,
+    pub(crate) exclude_authors: Vec<String>,
+    pub(crate) exclude_mine: bool,
+    pub(crate) anonymize_authors: bool,
+    pub(crate) sort_order: Option<SortOrder>,
+    pub(crate) break_ties_by_hash: bool,
+    pub(crate) divergent_only: bool,
+    pub(crate) allow_unrelated: bool,
+    pub(crate) reject_detached_head: bool,
+    pub(crate) since_merge_base: bool,
+    pub(crate) normalize_whitespace: bool,
+    pub(crate) ignore_conventional_prefix: bool,
+    pub(crate) key_prefix: Option<KeyPrefix>,
+    pub(crate) max_count: Option<usize>,
+    pub(crate) skip: Option<usize>,
+    pub(crate) merge_filter: MergeFilter,
+    pub(crate) merge_summary_patterns: Option<Vec<String>>,
+    pub(crate) redaction_patterns: Vec<String>,
+    pub(crate) first_parent: bool,
+    pub(crate) paths: Vec<String>,
+    pub(crate) follow: bool,
+    pub(crate) include_types: Vec<String>,
+    pub(crate) revert_depth: usize,
+    pub(crate) dedupe: bool,
+    pub(crate) numstat: bool,
+    pub(crate) files: bool,
+    pub(crate) min_files: Option<usize>,
+    pub(crate) min_changed_lines: Option<usize>,
+    pub(crate) body: bool,
+    pub(crate) body_contains: Option<String>,
+    pub(crate) detect_squashes: bool,
+    pub(crate) extra_placeholders: Vec<String>,
+    pub(crate) record_separator: Option<char>,
+    pub(crate) field_separator: Option<char>,
+    pub(crate) relative_dates: bool,
+    pub(crate) commit_dates: bool,
+    pub(crate) full_hash: bool,
+    pub(crate) decorations: bool,
+    pub(crate) signatures: bool,
+    pub(crate) git_binary: Option<PathBuf>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) retries: usize,
+    pub(crate) verbosity: Verbosity,
+    pub(crate) fetch_first: bool,
+    pub(crate) require_clean: bool,
+    pub(crate) progress: Option<Progress>,
+}
+
+impl CompareOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leaves out the commits whose summary matches any of `words`. Blank entries, which every
+    /// summary would match, are ignored.
+    pub fn exclude(mut self, words: Vec<String>) -> Self {
+        self.exclude = words;
+        self
+    }
+
+    /// Only keeps the commits whose summary matches any of `words`, matched like the exclude
+    /// entries. A commit matching both is left out. Empty, the default, keeps every commit, and
+    /// so does a list of only blank entries.
+    pub fn include(mut self, words: Vec<String>) -> Self {
+        self.include = words;
+        self
+    }
+
+    pub fn exclude_mode(mut self, exclude_mode: ExcludeMode) -> Self {
+        self.exclude_mode = exclude_mode;
+        self
+    }
+
+    /// Ignores case when matching the exclude and include entries.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Only matches the exclude and include entries as whole words, bounded by whitespace,
+    /// punctuation or the ends of the summary, so excluding `fix` keeps `prefix`. Regular
+    /// expressions are matched as they are.
+    pub fn whole_word(mut self, whole_word: bool) -> Self {
+        self.whole_word = whole_word;
+        self
+    }
+
+    /// Runs git in `repo_path` instead of the current directory.
+    pub fn repo_path(mut self, repo_path: impl Into<PathBuf>) -> Self {
+        self.repo_path = Some(repo_path.into());
+        self
+    }
+
+    pub fn match_strategy(mut self, match_strategy: MatchStrategy) -> Self {
+        self.match_strategy = match_strategy;
+        self
+    }
+
+    /// Only reads the commits more recent than `date`, in any format `git log --since` accepts.
+    pub fn since(mut self, date: impl Into<String>) -> Self {
+        self.since = Some(date.into());
+        self
+    }
+
+    /// Only reads the commits older than `date`, in any format `git log --until` accepts.
+    pub fn until(mut self, date: impl Into<String>) -> Self {
+        self.until = Some(date.into());
+        self
+    }
+
+    /// Applies `since` and `until` to the dates of `date_kind`, the commit dates by default.
+    /// With `DateKind::Author`, the commits are filtered after `git log` reads them, so
+    /// `max_count` and `skip` count the ones outside the range too.
+    pub fn date_kind(mut self, date_kind: DateKind) -> Self {
+        self.date_kind = date_kind;
+        self
+    }
+
+    /// Only reads the commits whose author name or email matches `pattern`. `git log --author`
+    /// takes it as a basic regular expression, while the `git2` feature matches it against
+    /// `name <email>` with the `regex` crate, so patterns using `\(`, `\|` or lookarounds
+    /// don't match the same commits there.
+    pub fn author(mut self, pattern: impl Into<String>) -> Self {
+        self.author = Some(pattern.into());
+        self
+    }
+
+    /// Leaves out the commits whose author name or email, ignoring case, is any of `authors`,
+    /// such as the bots of a CI, whatever their summary.
+    pub fn exclude_authors(mut self, authors: Vec<String>) -> Self {
+        self.exclude_authors = authors;
+        self
+    }
+
+    /// Also leaves out the commits authored with the `user.email` configured for the
+    /// repository, failing with `DiffError::UserEmailNotSet` if there's none.
+    pub fn exclude_mine(mut self, exclude_mine: bool) -> Self {
+        self.exclude_mine = exclude_mine;
+        self
+    }
+
+    pub(crate) fn reads_email(&self) -> bool {
+        !self.exclude_authors.is_empty() || self.exclude_mine || self.anonymize_authors
+    }
+
+    /// Replaces the author of the returned commits with a pseudonym like `author-3`, numbered in
+    /// the order the authors are first returned, and clears their emails, so reports can be
+    /// shared without their names. Authors are told apart by email, and keep their pseudonym
+    /// across both sides of a comparison but not from one comparison to the next. Authors are
+    /// still excluded by their real names and emails.
+    pub fn anonymize_authors(mut self, anonymize_authors: bool) -> Self {
+        self.anonymize_authors = anonymize_authors;
+        self
+    }
+
+    pub fn sort_order(mut self, sort_order: SortOrder) -> Self {
+        self.sort_order = Some(sort_order);
+        self
+    }
+
+    /// Sorts the commits sharing a date by hash, in ascending order whatever the sort order,
+    /// rather than in the order git listed them. The order is then the same on every machine and
+    /// git version, such as for golden files. Only applies when the commits are sorted.
+    pub fn break_ties_by_hash(mut self, break_ties_by_hash: bool) -> Self {
+        self.break_ties_by_hash = break_ties_by_hash;
+        self
+    }
+
+    /// Lets git skip the history both refs share. Commits are still matched afterwards, so
+    /// cherry-picks are detected, but a commit is no longer matched against a shared commit that
+    /// happens to have the same summary.
+    pub fn divergent_only(mut self, divergent_only: bool) -> Self {
+        self.divergent_only = divergent_only;
+        self
+    }
+
+    /// Only reads the commits of each ref made after `git merge-base` of the refs, skipping the
+    /// history they share. Unlike `divergent_only`, the commits of a ref that the other merged in
+    /// since are still read. Refs without a merge base are compared in full, with a warning.
+    pub fn since_merge_base(mut self, since_merge_base: bool) -> Self {
+        self.since_merge_base = since_merge_base;
+        self
+    }
+
+    /// Compares refs without a common ancestor instead of failing with
+    /// `DiffError::NoCommonAncestor`, like `git merge --allow-unrelated-histories`. Every commit
+    /// of such refs is likely to look unique.
+    pub fn allow_unrelated(mut self, allow_unrelated: bool) -> Self {
+        self.allow_unrelated = allow_unrelated;
+        self
+    }
+
+    /// Fails with `DiffError::DetachedHead` when a ref, or an end of a range, is `HEAD` and it's
+    /// detached, such as during a rebase or a bisect, where it names whichever commit is checked
+    /// out rather than a branch.
+    pub fn reject_detached_head(mut self, reject_detached_head: bool) -> Self {
+        self.reject_detached_head = reject_detached_head;
+        self
+    }
+
+    /// Ignores differences in whitespace when matching summaries.
+    pub fn normalize_whitespace(mut self, normalize_whitespace: bool) -> Self {
+        self.normalize_whitespace = normalize_whitespace;
+        self
+    }
+
+    /// Matches Conventional Commits summaries by their description, so `feat: add cache` matches
+    /// `feat(cache): add cache`. The summaries are still returned in full.
+    pub fn ignore_conventional_prefix(mut self, ignore_conventional_prefix: bool) -> Self {
+        self.ignore_conventional_prefix = ignore_conventional_prefix;
+        self
+    }
+
+    /// Matches summaries by their prefix only, so `ABC-123: add cache` matches
+    /// `ABC-123: cache things` with `KeyPrefix::Delimiter(":".into())`. The summaries are still
+    /// returned in full.
+    pub fn key_prefix(mut self, key_prefix: KeyPrefix) -> Self {
+        self.key_prefix = Some(key_prefix);
+        self
+    }
+
+    /// Only reads the newest `max_count` commits of each ref, so a commit can look unique when
+    /// its match on the other ref is older than the window.
+    pub fn max_count(mut self, max_count: usize) -> Self {
+        self.max_count = Some(max_count);
+        self
+    }
+
+    /// Skips the newest `skip` commits of each ref before `max_count` applies, so both page
+    /// through a long history together. Both refs are paged, so a commit is only matched against
+    /// the same page of the other ref and any commit whose match is on another page looks unique.
+    pub fn skip(mut self, skip: usize) -> Self {
+        self.skip = Some(skip);
+        self
+    }
+
+    pub fn merge_filter(mut self, merge_filter: MergeFilter) -> Self {
+        self.merge_filter = merge_filter;
+        self
+    }
+
+    /// Leaves out the commits whose summary looks like the one of a merge, such as
+    /// `Merge pull request #123 from user/branch`, matched with `DEFAULT_MERGE_SUMMARY_PATTERNS`.
+    /// Unlike `MergeFilter::NoMerges`, this goes by the summary, so it also drops the commits
+    /// that quote a merge summary, and keeps the merges whose summary was reworded.
+    pub fn skip_merge_summaries(mut self, skip_merge_summaries: bool) -> Self {
+        self.merge_summary_patterns = skip_merge_summaries.then(|| {
+            DEFAULT_MERGE_SUMMARY_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect()
+        });
+        self
+    }
+
+    /// Like `skip_merge_summaries`, but recognizes the merge summaries with the regular
+    /// expressions `patterns` instead of the default ones.
+    pub fn merge_summary_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.merge_summary_patterns = Some(patterns);
+        self
+    }
+
+    /// Replaces the text matching any of `DEFAULT_REDACTION_PATTERNS` in the summaries and bodies
+    /// of the returned commits with `***`, such as tokens pasted by mistake, so reports can be
+    /// shared. The commits are still matched and excluded by their original summaries.
+    pub fn redact_secrets(mut self, redact_secrets: bool) -> Self {
+        self.redaction_patterns = if redact_secrets {
+            DEFAULT_REDACTION_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        self
+    }
+
+    /// Like `redact_secrets`, but masks the text matching the regular expressions `patterns`
+    /// instead of the default ones, such as internal URLs. Empty, the default, masks nothing.
+    pub fn redaction_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.redaction_patterns = patterns;
+        self
+    }
+
+    /// Only follows the first parent of merge commits, like `git log --first-parent`. The merges
+    /// are still read, but not the commits they brought in.
+    pub fn first_parent(mut self, first_parent: bool) -> Self {
+        self.first_parent = first_parent;
+        self
+    }
+
+    /// Only reads the commits that touch any of `paths`, relative to the repository path or the
+    /// current directory like with `git log`. Paths that don't exist match no commits.
+    pub fn paths(mut self, paths: Vec<String>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Follows the history of the path past its renames, like `git log --follow`. Git only
+    /// follows a single path, so reading the commits fails with `DiffError::FollowNeedsOnePath`
+    /// unless `paths` has exactly one. `count_unique` doesn't follow renames.
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Only keeps the unique commits whose Conventional Commits type, ignoring case, is one of
+    /// `types`. Commits that don't follow Conventional Commits are left out too. Matching is
+    /// unaffected, so this only narrows down the result.
+    pub fn include_types(mut self, types: Vec<String>) -> Self {
+        self.include_types = types;
+        self
+    }
+
+    /// Leaves out the unique commits that `git revert` undid along with their reverts. Reverts
+    /// nested deeper than `max_depth` are kept, so with `1` a `Revert "Revert "X""` is listed
+    /// since it reapplies `X`. `0`, the default, keeps every revert.
+    pub fn collapse_reverts(mut self, max_depth: usize) -> Self {
+        self.revert_depth = max_depth;
+        self
+    }
+
+    /// Lists a summary at most once among the unique commits of each ref, keeping its most
+    /// recent commit. Summaries are compared as they are matched, whatever the match strategy.
+    /// Off by default since the repeats can matter, like a fix applied twice.
+    pub fn dedupe(mut self, dedupe: bool) -> Self {
+        self.dedupe = dedupe;
+        self
+    }
+
+    /// Counts the lines each commit adds and removes. Off by default since it makes git compute
+    /// every diff.
+    pub fn numstat(mut self, numstat: bool) -> Self {
+        self.numstat = numstat;
+        self
+    }
+
+    /// Reads the paths each commit changed into `Commit::files`, as listed by `git log
+    /// --numstat`, so the lines are counted too. Renamed files are listed by their new path.
+    pub fn files(mut self, files: bool) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// Leaves out the commits changing fewer than `min_files` files, such as to keep a changelog
+    /// to the substantial changes. The commits without a diff, merges and empty commits, are
+    /// kept. The changed files are read like with `numstat`.
+    pub fn min_files(mut self, min_files: usize) -> Self {
+        self.min_files = Some(min_files);
+        self
+    }
+
+    /// Leaves out the commits adding and removing fewer than `min_changed_lines` lines in all,
+    /// such as typo fixes and version bumps. Like `min_files`, the commits without a diff are
+    /// kept.
+    pub fn min_changed_lines(mut self, min_changed_lines: usize) -> Self {
+        self.min_changed_lines = Some(min_changed_lines);
+        self
+    }
+
+    pub(crate) fn reads_numstat(&self) -> bool {
+        self.numstat || self.files || self.min_files.is_some() || self.min_changed_lines.is_some()
+    }
+
+    /// Reads the message body of each commit into `Commit::body`. Off by default since the
+    /// output then needs more parsing.
+    pub fn body(mut self, body: bool) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// Only keeps the unique commits whose body contains `text`, leaving out the ones without a
+    /// body. The bodies are read even without the `body` option.
+    pub fn body_contains(mut self, text: impl Into<String>) -> Self {
+        self.body_contains = Some(text.into());
+        self
+    }
+
+    /// Sets `Commit::squashed_into` on the unique commits whose summary is a line of the body of
+    /// a commit of the other ref, as squash merges list the commits they squash. It's a guess: a
+    /// squash merge whose message was edited isn't detected. The bodies are read even without the
+    /// `body` option.
+    pub fn detect_squashes(mut self, detect_squashes: bool) -> Self {
+        self.detect_squashes = detect_squashes;
+        self
+    }
+
+    /// Adds `git log` format placeholders such as `%ae` for the author email, whose expansions
+    /// go to `Commit::extra` in the same order. They can span several lines, but shouldn't
+    /// expand to the field separator.
+    pub fn extra_placeholders(mut self, placeholders: Vec<String>) -> Self {
+        self.extra_placeholders = placeholders;
+        self
+    }
+
+    /// Separates the commits of the `git log` output with `separator` instead of NUL, which git
+    /// cuts commit messages at, so no message can contain it. It has to be an ASCII control
+    /// character the output uses for nothing else, or reading the log fails with
+    /// `DiffError::InvalidSeparator`.
+    pub fn record_separator(mut self, separator: char) -> Self {
+        self.record_separator = Some(separator);
+        self
+    }
+
+    /// Separates the hash, date, author, summary and body of each commit of the `git log` output
+    /// with `separator` instead of the ASCII unit separator, such as for summaries that contain
+    /// it. It has to be an ASCII control character like for `record_separator`.
+    pub fn field_separator(mut self, separator: char) -> Self {
+        self.field_separator = Some(separator);
+        self
+    }
+
+    /// Also reads the dates relative to now, such as `3 days ago`, into `Commit::relative_date`.
+    /// The dates are still read, and sorted by, as `%Y-%m-%d`.
+    pub fn relative_dates(mut self, relative_dates: bool) -> Self {
+        self.relative_dates = relative_dates;
+        self
+    }
+
+    /// Also reads the commit dates into `Commit::commit_date`, formatted like the author dates.
+    pub fn commit_dates(mut self, commit_dates: bool) -> Self {
+        self.commit_dates = commit_dates;
+        self
+    }
+
+    /// Reads the full 40 character hashes instead of the abbreviated ones, which can become
+    /// ambiguous in large repositories.
+    pub fn full_hash(mut self, full_hash: bool) -> Self {
+        self.full_hash = full_hash;
+        self
+    }
+
+    /// Reads the names of the branches and tags pointing at each commit into `Commit::refs`, as
+    /// `git log --decorate` shows them.
+    pub fn decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    pub(crate) fn reads_tree(&self) -> bool {
+        self.match_strategy == MatchStrategy::TreeHash
+    }
+
+    /// Reads whether each commit is signed and its signature checks out into
+    /// `Commit::signature`. Off by default since git runs `gpg` or `ssh-keygen` for every signed
+    /// commit.
+    pub fn signatures(mut self, signatures: bool) -> Self {
+        self.signatures = signatures;
+        self
+    }
+
+    pub(crate) fn reads_body(&self) -> bool {
+        self.body || self.body_contains.is_some() || self.detect_squashes
+    }
+
+    /// Runs the git executable at `git_binary` instead of the one on `PATH`.
+    pub fn git_binary(mut self, git_binary: impl Into<PathBuf>) -> Self {
+        self.git_binary = Some(git_binary.into());
+        self
+    }
+
+    /// Kills any git command that runs longer than `timeout`, failing with `DiffError::Timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Runs `git log` up to `retries` more times, waiting longer each time, when it fails in a
+    /// way that can go away on its own, like a lock held by another git process. Failures like
+    /// an unknown revision fail right away.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Prints every git command to stderr before running it, along with how many commits were
+    /// read and left out. The same as `Verbosity::Verbose`, and `Verbosity::Normal` when false.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbosity = if verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        };
+        self
+    }
+
+    /// Sets how much is printed to stderr, only the warnings by default.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Runs `git fetch` for the remotes of remote-tracking refs like `origin/main` before
+    /// comparing, so they aren't stale. Those refs are compared as they are without it.
+    pub fn fetch_first(mut self, fetch_first: bool) -> Self {
+        self.fetch_first = fetch_first;
+        self
+    }
+
+    /// Fails with `DiffError::DirtyWorkingTree` before comparing when tracked files have
+    /// uncommitted changes, staged or not, such as in a release script where `HEAD` has to mean
+    /// what was committed. Untracked files are ignored.
+    pub fn require_clean(mut self, require_clean: bool) -> Self {
+        self.require_clean = require_clean;
+        self
+    }
+
+    /// Calls `progress` as the commits of each ref are fetched and once they are compared, so
+    /// long comparisons can show a spinner or a progress bar. Both refs are fetched at the same
+    /// time, so it can be called from two threads; use a `Mutex` to keep state in it.
+    pub fn progress(mut self, progress: impl Fn(ProgressEvent) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Progress(Arc::new(progress)));
+        self
+    }
+
+    pub(crate) fn report(&self, event: ProgressEvent) {
+        if let Some(Progress(progress)) = &self.progress {
+            progress(event);
+        }
+    }
+}
+
+/// The environment variable holding exclude entries shared by every comparison, separated by
+/// commas.
+pub const EXCLUDE_ENV_VAR: &str = "DIFF_EXCLUDE";
+
+/// Reads the exclude entries of the `DIFF_EXCLUDE` environment variable, trimmed and without
+/// empty ones. Empty if it isn't set.
+pub fn env_excludes() -> Vec<String> {
+    let Some(value) = std::env::var_os(EXCLUDE_ENV_VAR) else {
+        return Vec::new();
+    };
+
+    value
+        .to_string_lossy()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reads exclude entries from the file at `path`, one per line. Blank lines and lines starting
+/// with `#` are skipped, and the entries are trimmed.
+pub fn load_excludes(path: &Path) -> Result<Vec<String>, DiffError> {
+    let contents = fs::read_to_string(path)?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_strategy_parses_fuzzy_thresholds_in_range() {
+        assert!(matches!(
+            "fuzzy:0.8".parse(),
+            Ok(MatchStrategy::Fuzzy { threshold }) if threshold == 0.8
+        ));
+        assert!("fuzzy:1".parse::<MatchStrategy>().is_ok());
+
+        for value in ["fuzzy:1.5", "fuzzy:-0.1", "fuzzy:NaN", "fuzzy:"] {
+            assert!(value.parse::<MatchStrategy>().is_err(), "{value}");
+        }
+    }
+}
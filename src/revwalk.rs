@@ -0,0 +1,247 @@
+//! Reads the repository with libgit2 instead of running `git`, for the `git2` feature.
+//!
+//! Only the log itself is read here. Patch ids and `Change-Id:` trailers still come from `git`.
+
+use std::path::Path;
+
+use git2::{Repository, Sort, Time};
+use regex::Regex;
+
+use crate::error::DiffError;
+use crate::git::{Commit, FIELD_SEPARATOR};
+use crate::options::{CompareOptions, MergeFilter};
+
+/// Whether every option of `opts` is implemented here. `since` and `until` take any date `git`
+/// understands, `paths` relies on its history simplification, and the relative dates, the
+/// decorations, the signatures and the extra placeholders are formatted by `git log`, so those
+/// are left to it.
+pub(crate) fn supports(opts: &CompareOptions) -> bool {
+    opts.since.is_none()
+        && opts.until.is_none()
+        && opts.paths.is_empty()
+        && !opts.follow
+        && !opts.relative_dates
+        && !opts.decorations
+        && !opts.signatures
+        && opts.extra_placeholders.is_empty()
+}
+
+pub(crate) fn ensure_repo(repo_path: &Path) -> Result<(), DiffError> {
+    let repo = Repository::discover(repo_path).map_err(|_| DiffError::NotARepo)?;
+    repo.workdir().ok_or(DiffError::NotARepo)?;
+
+    Ok(())
+}
+
+pub(crate) fn verify_ref(repo_path: &str, ref_: &str) -> Result<(), DiffError> {
+    let repo = Repository::discover(repo_path)?;
+
+    repo.revparse_single(ref_)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| DiffError::RefNotFound(ref_.to_string()))?;
+
+    Ok(())
+}
+
+/// Walks `revision` like `get_branch_commits` does, newest first.
+pub(crate) fn branch_commits(
+    repo_path: &str,
+    revision: &str,
+    opts: &CompareOptions,
+) -> Result<Vec<Commit>, DiffError> {
+    let repo = Repository::discover(repo_path)?;
+
+    let mut revwalk = repo.revwalk()?;
+    // Like `git log`, children come before their parents, even when their dates are the same.
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    if opts.first_parent {
+        revwalk.simplify_first_parent()?;
+    }
+    if revision.contains("..") {
+        revwalk.push_range(revision)?;
+    } else {
+        revwalk.push(repo.revparse_single(revision)?.peel_to_commit()?.id())?;
+    }
+
+    // Like `git log --author`, the pattern is matched against both the name and the email, but
+    // with the `regex` syntax rather than git's basic regular expressions.
+    let author = opts
+        .author
+        .as_deref()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|source| DiffError::InvalidPattern {
+                pattern: pattern.to_string(),
+                source,
+            })
+        })
+        .transpose()?;
+
+    let mut commits = Vec::new();
+    let mut skipped = 0;
+
+    for oid in revwalk {
+        if opts.max_count == Some(commits.len()) {
+            break;
+        }
+
+        let commit = repo.find_commit(oid?)?;
+
+        let is_merge = commit.parent_count() > 1;
+        match opts.merge_filter {
+            MergeFilter::NoMerges if is_merge => continue,
+            MergeFilter::OnlyMerges if !is_merge => continue,
+            _ => {}
+        }
+
+        let signature = commit.author();
+        let name = decode(signature.name_bytes(), None);
+
+        let email = decode(signature.email_bytes(), None);
+        if let Some(author) = &author {
+            if !author.is_match(&format!("{name} <{email}>")) {
+                continue;
+            }
+        }
+
+        // Like `--skip`, only the commits that pass the filters are counted.
+        if opts.skip.is_some_and(|skip| skipped < skip) {
+            skipped += 1;
+            continue;
+        }
+
+        let hash = if opts.full_hash {
+            commit.id().to_string()
+        } else {
+            commit
+                .as_object()
+                .short_id()?
+                .as_str()
+                .unwrap_or_default()
+                .to_string()
+        };
+        let summary = decode(
+            commit.summary_bytes().unwrap_or_default(),
+            commit.message_encoding()?,
+        );
+
+        let line =
+            [hash, format_date(signature.when()), name, summary].join(&FIELD_SEPARATOR.to_string());
+        let mut parsed = Commit::new(line).ok_or_else(|| {
+            DiffError::Parse(format!("commit {} is missing a field", commit.id()))
+        })?;
+
+        if opts.reads_email() {
+            parsed.email = Some(email);
+        }
+
+        if opts.commit_dates {
+            parsed.commit_date = Some(format_date(commit.committer().when()));
+        }
+
+        if opts.reads_tree() {
+            parsed.tree = Some(commit.tree_id().to_string());
+        }
+
+        if opts.reads_body() {
+            let body = decode(
+                commit.body_bytes().unwrap_or_default(),
+                commit.message_encoding()?,
+            );
+            let body = body.trim();
+            if !body.is_empty() {
+                parsed.body = Some(body.to_string());
+            }
+        }
+
+        // `git log --numstat` doesn't show a diff for merges either.
+        if opts.reads_numstat() && !is_merge {
+            let parent_tree = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            let mut diff =
+                repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+            // Like `git log`, which detects renames by default, so a renamed file is listed once.
+            diff.find_similar(None)?;
+            let stats = diff.stats()?;
+            parsed.additions = stats.insertions();
+            parsed.deletions = stats.deletions();
+            parsed.files = diff
+                .deltas()
+                .filter_map(|delta| delta.new_file().path())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+        }
+
+        commits.push(parsed);
+    }
+
+    Ok(commits)
+}
+
+/// Decodes a message, re-encoding Latin-1 ones as `git log --encoding=UTF-8` does. Other
+/// encodings are read as UTF-8.
+fn decode(bytes: &[u8], encoding: Option<&str>) -> String {
+    match encoding {
+        Some(encoding)
+            if encoding.eq_ignore_ascii_case("ISO-8859-1")
+                || encoding.eq_ignore_ascii_case("latin1") =>
+        {
+            bytes.iter().map(|&byte| char::from(byte)).collect()
+        }
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Formats the date as `%Y-%m-%d` in the timezone of the author, like `--date=format:` does.
+fn format_date(time: Time) -> String {
+    let seconds = time.seconds() + i64::from(time.offset_minutes()) * 60;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+// Howard Hinnant's `civil_from_days`, turning days since 1970-01-01 into a proleptic Gregorian
+// date.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_from_days_counts_from_the_epoch() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        assert_eq!(civil_from_days(-25_508), (1900, 3, 1));
+    }
+
+    #[test]
+    fn format_date_uses_the_author_timezone() {
+        // 2024-01-01 23:30 UTC, which is already the next day at UTC+1.
+        let seconds = 19_723 * 86_400 + 23 * 3600 + 30 * 60;
+
+        assert_eq!(format_date(Time::new(seconds, 0)), "2024-01-01");
+        assert_eq!(format_date(Time::new(seconds, 60)), "2024-01-02");
+        assert_eq!(format_date(Time::new(seconds, -60)), "2024-01-01");
+    }
+}